@@ -0,0 +1,242 @@
+use crate::common::{ms_to_kmh, station_id_of, BoundingBox, Measurement, Station};
+
+use chrono::NaiveDateTime;
+use encoding_rs::{ISO_8859_15, UTF_8};
+use serde::Deserialize;
+use spin_sdk::http::{Method, Request, Response};
+use std::collections::HashMap;
+
+const API_URL: &str = "https://opendata.aemet.es/opendata/api/observacion/convencional/todas";
+const STATION_BASE_URL: &str = "https://www.aemet.es/es/eltiempo/observacion/ultimosdatos";
+
+// AEMET replies with a link to the actual data.
+#[derive(Debug, Deserialize)]
+struct EnvelopeRaw {
+    estado: u16,
+    descripcion: String,
+    datos: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct ObservationRaw {
+    idema: String,
+    ubi: String,
+    lat: f64,
+    lon: f64,
+    alt: Option<f64>,
+    fint: String,
+    vv: Option<f64>,
+    dv: Option<f64>,
+    vmax: Option<f64>,
+    dmax: Option<f64>,
+    ta: Option<f64>,
+    hr: Option<f64>,
+    pres: Option<f64>,
+    prec: Option<f64>,
+}
+
+pub fn api_key() -> anyhow::Result<Option<String>> {
+    let api_key = spin_sdk::variables::get("aemet_api_key")?;
+    Ok((!api_key.is_empty()).then_some(api_key))
+}
+
+pub async fn fetch_data(api_key: &str) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+    println!("[aemet] Fetching...");
+    let bbox = BoundingBox::parse(&spin_sdk::variables::get("aemet_bbox")?)?;
+
+    let request = Request::builder()
+        .method(Method::Get)
+        .uri(API_URL)
+        .header("api_key", api_key)
+        .build();
+    let response: Response = spin_sdk::http::send(request).await?;
+    let (body, _, _) = UTF_8.decode(response.body());
+    let datos_url = parse_envelope(&body)?;
+
+    let request = Request::builder()
+        .method(Method::Get)
+        .uri(datos_url)
+        .build();
+    let response: Response = spin_sdk::http::send(request).await?;
+    let (body, _, decoding_errors) = ISO_8859_15.decode(response.body());
+    if decoding_errors {
+        println!("Decoding errors found");
+    }
+
+    println!("Analyzing...");
+    let observations = parse_observations(&body)?;
+    Ok(convert_observations(observations, &bbox))
+}
+
+fn parse_envelope(body: &str) -> anyhow::Result<String> {
+    let envelope: EnvelopeRaw = serde_json::from_str(body)?;
+    if envelope.estado != 200 {
+        anyhow::bail!(
+            "AEMET request failed ({}): {}",
+            envelope.estado,
+            envelope.descripcion
+        );
+    }
+    envelope
+        .datos
+        .ok_or_else(|| anyhow::anyhow!("No data link in AEMET response"))
+}
+
+fn parse_observations(body: &str) -> anyhow::Result<Vec<ObservationRaw>> {
+    Ok(serde_json::from_str(body)?)
+}
+
+fn convert_observations(
+    observations: Vec<ObservationRaw>,
+    bbox: &BoundingBox,
+) -> (Vec<Station>, Vec<Measurement>) {
+    // Observations of the last hours are listed, only the latest one is relevant.
+    let mut latest: HashMap<String, ObservationRaw> = HashMap::new();
+    for observation in observations {
+        if !bbox.contains(observation.lat, observation.lon) {
+            continue;
+        }
+        match latest.get(&observation.idema) {
+            Some(other) if other.fint >= observation.fint => {}
+            _ => {
+                latest.insert(observation.idema.to_owned(), observation);
+            }
+        }
+    }
+
+    let mut available_stations: Vec<Station> = vec![];
+    let mut measurements: Vec<Measurement> = vec![];
+
+    for (vendor_id, observation) in latest {
+        let wind_speed = if let Some(vv) = observation.vv {
+            ms_to_kmh(vv).round() as u64
+        } else {
+            continue;
+        };
+
+        let last_update =
+            match NaiveDateTime::parse_from_str(&observation.fint, "%Y-%m-%dT%H:%M:%S") {
+                Ok(fint) => fint.format("%Y-%m-%dT%H:%MZ").to_string(),
+                Err(e) => {
+                    println!("[{}] Invalid time '{}': {}", vendor_id, observation.fint, e);
+                    continue;
+                }
+            };
+
+        let station_url = format!("{}?l={}", STATION_BASE_URL, vendor_id);
+        let station_id = station_id_of("aemet", &vendor_id);
+
+        let measurement = Measurement {
+            station_id: station_id.clone(),
+            wind_speed,
+            wind_direction: observation.dv.filter(|_| wind_speed != 0),
+            gusts_speed: observation.vmax.map(|v| ms_to_kmh(v).round() as u64),
+            gusts_direction: observation.dmax,
+            temperature: observation.ta,
+            humidity: observation.hr,
+            pressure: observation.pres,
+            precipitation: observation.prec,
+            last_update,
+            ..Default::default()
+        };
+        let station = Station {
+            id: station_id,
+            name: observation.ubi,
+            elevation: observation.alt.map(|alt| alt.round() as i64),
+            url: station_url,
+            lat: observation.lat,
+            long: observation.lon,
+            available: true,
+            provider: "aemet".to_owned(),
+            vendor_id: vendor_id.to_owned(),
+            alternates: vec![],
+        };
+        available_stations.push(station);
+        measurements.push(measurement);
+    }
+
+    println!("Found {} available stations", available_stations.len());
+    (available_stations, measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn catalonia() -> BoundingBox {
+        BoundingBox::parse("40.5,0.1,42.9,3.4").unwrap()
+    }
+
+    #[test]
+    fn parse_envelope_returns_data_link() {
+        let body = include_str!("../tests/fixtures/aemet_envelope.json");
+        assert_eq!(
+            parse_envelope(body).unwrap(),
+            "https://opendata.aemet.es/opendata/sh/5b2b0c8a"
+        );
+    }
+
+    #[test]
+    fn parse_envelope_reports_failure() {
+        let body = include_str!("../tests/fixtures/aemet_envelope_unauthorized.json");
+        let e = parse_envelope(body).unwrap_err();
+        assert_eq!(
+            e.to_string(),
+            "AEMET request failed (401): API key invalido"
+        );
+    }
+
+    #[test]
+    fn parse_observations_reads_all_entries() {
+        let body = include_str!("../tests/fixtures/aemet_observations.json");
+        let observations = parse_observations(body).unwrap();
+        assert_eq!(observations.len(), 6);
+        assert_eq!(observations[0].idema, "0076");
+        assert_eq!(observations[4].vv, None);
+    }
+
+    #[test]
+    fn convert_observations_keeps_latest_within_bbox() {
+        let body = include_str!("../tests/fixtures/aemet_observations.json");
+        let (stations, measurements) =
+            convert_observations(parse_observations(body).unwrap(), &catalonia());
+
+        // Madrid is outside the bounding box, Arenys de Mar has no wind.
+        let mut vendor_ids = stations
+            .iter()
+            .map(|s| s.vendor_id.as_str())
+            .collect::<Vec<&str>>();
+        vendor_ids.sort();
+        assert_eq!(vendor_ids, ["0076", "9981A"]);
+
+        let airport_id = station_id_of("aemet", "0076");
+        let airport = stations.iter().find(|s| s.id == airport_id).unwrap();
+        assert_eq!(airport.name, "BARCELONA AEROPUERTO");
+        assert_eq!(airport.elevation, Some(4));
+
+        let measurement = measurements
+            .iter()
+            .find(|m| m.station_id == airport_id)
+            .unwrap();
+        assert_eq!(measurement.last_update, "2024-05-12T10:00Z");
+        // 5.0 m/s and 8.3 m/s
+        assert_eq!(measurement.wind_speed, 18);
+        assert_eq!(measurement.gusts_speed, Some(30));
+        assert_eq!(measurement.wind_direction, Some(200.0));
+        assert_eq!(measurement.temperature, Some(19.1));
+    }
+
+    #[test]
+    fn convert_observations_drops_direction_of_calm() {
+        let body = include_str!("../tests/fixtures/aemet_observations.json");
+        let (_, measurements) =
+            convert_observations(parse_observations(body).unwrap(), &catalonia());
+        let tortosa_id = station_id_of("aemet", "9981A");
+        let measurement = measurements
+            .iter()
+            .find(|m| m.station_id == tortosa_id)
+            .unwrap();
+        assert_eq!(measurement.wind_speed, 0);
+        assert_eq!(measurement.wind_direction, None);
+    }
+}
@@ -0,0 +1,183 @@
+use crate::history::{Sample, RETENTION_HOURS};
+use crate::wind::circular_mean;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::Serialize;
+
+#[derive(Debug, Serialize)]
+pub struct Aggregates {
+    pub station_id: String,
+    pub since: String,
+    pub until: String,
+    pub samples_count: usize,
+    pub wind_speed_avg: Option<f64>,
+    // Vector average weighted by speeds, so that N and NNW don't average to S.
+    pub wind_direction_avg: Option<f64>,
+    pub gusts_speed_max: Option<u64>,
+    pub temperature_min: Option<f64>,
+    pub temperature_max: Option<f64>,
+}
+
+// Parses e.g. "45m", "1h" or "1h30m".
+pub fn parse_window(window: &str) -> anyhow::Result<Duration> {
+    let invalid = || anyhow::anyhow!("Invalid window '{}', e.g. '30m' or '1h' expected", window);
+    let (hours, minutes) = match window.split_once('h') {
+        Some((hours, minutes)) => (hours, minutes),
+        None => ("0", window),
+    };
+    let hours: i64 = hours.parse().map_err(|_| invalid())?;
+    let minutes: i64 = match minutes {
+        "" => 0,
+        minutes => minutes
+            .strip_suffix('m')
+            .and_then(|minutes| minutes.parse().ok())
+            .ok_or_else(invalid)?,
+    };
+    let duration = Duration::hours(hours) + Duration::minutes(minutes);
+    if duration <= Duration::zero() || duration > Duration::hours(RETENTION_HOURS) {
+        anyhow::bail!(
+            "Window '{}' out of range, up to {} hours are kept",
+            window,
+            RETENTION_HOURS
+        );
+    }
+    Ok(duration)
+}
+
+fn round1(value: f64) -> f64 {
+    (value * 10.0).round() / 10.0
+}
+
+// Aggregates the samples measured in the window ending now.
+pub fn aggregate(
+    station_id: &str,
+    samples: &[Sample],
+    window: Duration,
+    now: DateTime<Utc>,
+) -> Aggregates {
+    let since = now - window;
+    let samples: Vec<&Sample> = samples
+        .iter()
+        .filter(|sample| {
+            sample
+                .time()
+                .is_some_and(|time| time > since && time <= now)
+        })
+        .collect();
+
+    let wind_speeds: Vec<f64> = samples
+        .iter()
+        .filter_map(|sample| sample.wind_speed.map(|v| v as f64))
+        .collect();
+    let wind_speed_avg = (!wind_speeds.is_empty())
+        .then(|| round1(wind_speeds.iter().sum::<f64>() / wind_speeds.len() as f64));
+
+    let wind_direction_avg = circular_mean(
+        samples
+            .iter()
+            .filter_map(|sample| Some((sample.wind_direction?, sample.wind_speed? as f64))),
+    )
+    .map(|direction| direction.round());
+
+    let temperatures = samples.iter().filter_map(|sample| sample.temperature);
+
+    Aggregates {
+        station_id: station_id.to_owned(),
+        since: since.format("%Y-%m-%dT%H:%MZ").to_string(),
+        until: now.format("%Y-%m-%dT%H:%MZ").to_string(),
+        samples_count: samples.len(),
+        wind_speed_avg,
+        wind_direction_avg,
+        gusts_speed_max: samples.iter().filter_map(|sample| sample.gusts_speed).max(),
+        temperature_min: temperatures.clone().reduce(f64::min),
+        temperature_max: temperatures.reduce(f64::max),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-12T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn sample(
+        minutes_ago: i64,
+        wind_speed: u64,
+        wind_direction: f64,
+        gusts_speed: u64,
+        temperature: f64,
+    ) -> Sample {
+        Sample {
+            last_update: (now() - Duration::minutes(minutes_ago))
+                .format("%Y-%m-%dT%H:%MZ")
+                .to_string(),
+            wind_speed: Some(wind_speed),
+            wind_direction: Some(wind_direction),
+            gusts_speed: Some(gusts_speed),
+            temperature: Some(temperature),
+            humidity: None,
+            pressure: None,
+        }
+    }
+
+    #[test]
+    fn parse_window_of_valid_inputs() {
+        assert_eq!(parse_window("45m").unwrap(), Duration::minutes(45));
+        assert_eq!(parse_window("1h").unwrap(), Duration::hours(1));
+        assert_eq!(parse_window("1h30m").unwrap(), Duration::minutes(90));
+        assert_eq!(parse_window("24h").unwrap(), Duration::hours(24));
+    }
+
+    #[test]
+    fn parse_window_of_invalid_inputs() {
+        for window in [
+            "", "1", "h", "m", "1d", "1h30", "-1h", "0m", "25h", "1.5h", "30mm",
+        ] {
+            assert!(parse_window(window).is_err(), "{}", window);
+        }
+    }
+
+    #[test]
+    fn aggregate_over_window() {
+        let samples = [
+            // Outside of the window.
+            sample(90, 40, 180.0, 60, 25.0),
+            sample(50, 10, 200.0, 18, 14.5),
+            sample(30, 20, 220.0, 31, 16.0),
+            sample(10, 15, 210.0, 25, 15.2),
+        ];
+        let aggregates = aggregate("abc", &samples, Duration::hours(1), now());
+        assert_eq!(aggregates.since, "2024-05-12T11:00Z");
+        assert_eq!(aggregates.until, "2024-05-12T12:00Z");
+        assert_eq!(aggregates.samples_count, 3);
+        assert_eq!(aggregates.wind_speed_avg, Some(15.0));
+        assert_eq!(aggregates.gusts_speed_max, Some(31));
+        assert_eq!(aggregates.temperature_min, Some(14.5));
+        assert_eq!(aggregates.temperature_max, Some(16.0));
+        assert_eq!(aggregates.wind_direction_avg, Some(212.0));
+    }
+
+    #[test]
+    fn aggregate_direction_around_north() {
+        let samples = [
+            sample(20, 10, 350.0, 15, 10.0),
+            sample(10, 10, 10.0, 15, 10.0),
+        ];
+        let aggregates = aggregate("abc", &samples, Duration::hours(1), now());
+        assert_eq!(aggregates.wind_direction_avg.map(|d| d % 360.0), Some(0.0));
+    }
+
+    #[test]
+    fn aggregate_without_samples() {
+        let aggregates = aggregate("abc", &[], Duration::hours(1), now());
+        assert_eq!(aggregates.samples_count, 0);
+        assert_eq!(aggregates.wind_speed_avg, None);
+        assert_eq!(aggregates.wind_direction_avg, None);
+        assert_eq!(aggregates.gusts_speed_max, None);
+        assert_eq!(aggregates.temperature_min, None);
+    }
+}
@@ -0,0 +1,418 @@
+use crate::history::{History, Sample, RETENTION_HOURS};
+use crate::wind::direction_in_range;
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use spin_sdk::http::{Method, Request, Response};
+use spin_sdk::key_value::Store;
+
+const STORE_KEY: &str = "alerts:rules";
+
+// Latest measurement older than that doesn't match any rule.
+const MAX_SAMPLE_AGE_MINUTES: i64 = 60;
+// Samples further apart than that are a gap, conditions aren't known to
+// be met over it. Some providers report hourly.
+const MAX_SAMPLE_GAP_MINUTES: i64 = 60;
+
+// Conditions of a rule, e.g. "direction 180-240°, speed 10-25 km/h, for 30 minutes".
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct RuleConfig {
+    pub station_id: String,
+    // Range of directions may wrap around north, e.g. 300-30°.
+    direction_min: Option<f64>,
+    direction_max: Option<f64>,
+    speed_min: Option<f64>,
+    speed_max: Option<f64>,
+    #[serde(default)]
+    duration_minutes: i64,
+    webhook_url: String,
+}
+
+#[derive(Debug, Clone, Default, Serialize, Deserialize)]
+pub struct RuleState {
+    matching: bool,
+    // Time of the last change of `matching`.
+    since: Option<String>,
+}
+
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Rule {
+    pub id: String,
+    #[serde(flatten)]
+    pub config: RuleConfig,
+    #[serde(default)]
+    pub state: RuleState,
+}
+
+#[derive(Debug, Serialize)]
+struct Event<'a> {
+    rule_id: &'a str,
+    station_id: &'a str,
+    // "started" or "stopped".
+    event: &'static str,
+    time: String,
+    wind_speed: Option<u64>,
+    wind_direction: Option<f64>,
+    gusts_speed: Option<u64>,
+}
+
+// Hosts the webhooks may be on, so that the service can't be used
+// to send requests anywhere. They have to be allowed by `spin.toml` too.
+pub fn webhook_hosts() -> anyhow::Result<Vec<String>> {
+    let webhook_hosts = spin_sdk::variables::get("alert_webhook_hosts")?
+        .split(',')
+        .map(|host| host.trim().to_lowercase())
+        .filter(|host| !host.is_empty())
+        .collect();
+    Ok(webhook_hosts)
+}
+
+fn webhook_host_of(webhook_url: &str) -> Option<String> {
+    let authority = webhook_url
+        .strip_prefix("https://")?
+        .split(['/', '?', '#'])
+        .next()?;
+    // Credentials could disguise the host.
+    if authority.contains('@') {
+        return None;
+    }
+    let host = authority.split(':').next()?;
+    Some(host.to_lowercase())
+}
+
+impl RuleConfig {
+    pub fn validate(&self, webhook_hosts: &[String]) -> anyhow::Result<()> {
+        for direction in [self.direction_min, self.direction_max]
+            .into_iter()
+            .flatten()
+        {
+            if !(0.0..=360.0).contains(&direction) {
+                anyhow::bail!("Invalid direction: {}", direction);
+            }
+        }
+        if self.direction_min.is_some() != self.direction_max.is_some() {
+            anyhow::bail!("Both direction_min and direction_max expected");
+        }
+        if let (Some(speed_min), Some(speed_max)) = (self.speed_min, self.speed_max) {
+            if speed_min > speed_max {
+                anyhow::bail!("speed_min is greater than speed_max");
+            }
+        }
+        if !(0..=RETENTION_HOURS * 60).contains(&self.duration_minutes) {
+            anyhow::bail!(
+                "Invalid duration_minutes: {}, up to {} hours expected",
+                self.duration_minutes,
+                RETENTION_HOURS
+            );
+        }
+        self.check_webhook_url(webhook_hosts)
+    }
+
+    fn check_webhook_url(&self, webhook_hosts: &[String]) -> anyhow::Result<()> {
+        let host = webhook_host_of(&self.webhook_url)
+            .ok_or_else(|| anyhow::anyhow!("Invalid webhook_url, HTTPS URL expected"))?;
+        if !webhook_hosts.contains(&host) {
+            anyhow::bail!("Webhook host {} not allowed", host);
+        }
+        Ok(())
+    }
+
+    fn matches(&self, sample: &Sample) -> bool {
+        let Some(wind_speed) = sample.wind_speed.map(|v| v as f64) else {
+            return false;
+        };
+        if self
+            .speed_min
+            .is_some_and(|speed_min| wind_speed < speed_min)
+            || self
+                .speed_max
+                .is_some_and(|speed_max| wind_speed > speed_max)
+        {
+            return false;
+        }
+        match (self.direction_min, self.direction_max) {
+            (Some(direction_min), Some(direction_max)) => {
+                sample.wind_direction.is_some_and(|direction| {
+                    direction_in_range(direction, direction_min, direction_max)
+                })
+            }
+            _ => true,
+        }
+    }
+
+    // Conditions have to be met by all the samples of the duration, up to the
+    // latest one, without gaps between them.
+    fn is_met(&self, samples: &[Sample], now: DateTime<Utc>) -> bool {
+        let mut samples = samples
+            .iter()
+            .rev()
+            .filter_map(|sample| Some((sample.time()?, sample)));
+        let Some((latest_time, latest)) = samples.next() else {
+            return false;
+        };
+        if now - latest_time > Duration::minutes(MAX_SAMPLE_AGE_MINUTES) || !self.matches(latest) {
+            return false;
+        }
+        let mut matching_since = latest_time;
+        for (time, sample) in samples {
+            if matching_since - time > Duration::minutes(MAX_SAMPLE_GAP_MINUTES)
+                || !self.matches(sample)
+            {
+                break;
+            }
+            matching_since = time;
+        }
+        latest_time - matching_since >= Duration::minutes(self.duration_minutes)
+    }
+}
+
+pub struct Rules {
+    store: Store,
+    pub rules: Vec<Rule>,
+}
+
+pub fn load() -> anyhow::Result<Rules> {
+    let store = Store::open_default()?;
+    let rules = store.get_json::<Vec<Rule>>(STORE_KEY)?.unwrap_or_default();
+    Ok(Rules { store, rules })
+}
+
+impl Rules {
+    pub fn save(&self) -> anyhow::Result<()> {
+        self.store.set_json(STORE_KEY, &self.rules)
+    }
+
+    pub fn add(&mut self, config: RuleConfig, now: DateTime<Utc>) -> anyhow::Result<&Rule> {
+        let seed = format!(
+            "{}{:?}",
+            now.timestamp_nanos_opt().unwrap_or_default(),
+            config
+        );
+        let id = format!("{:x}", md5::compute(seed))[..16].to_owned();
+        self.rules.push(Rule {
+            id,
+            config,
+            state: RuleState::default(),
+        });
+        self.save()?;
+        Ok(&self.rules[self.rules.len() - 1])
+    }
+
+    // Returns false if there's no such rule. State of the rule is reset.
+    pub fn replace(&mut self, id: &str, config: RuleConfig) -> anyhow::Result<bool> {
+        let Some(rule) = self.rules.iter_mut().find(|rule| rule.id == id) else {
+            return Ok(false);
+        };
+        rule.config = config;
+        rule.state = RuleState::default();
+        self.save()?;
+        Ok(true)
+    }
+
+    // Returns false if there's no such rule.
+    pub fn remove(&mut self, id: &str) -> anyhow::Result<bool> {
+        let count = self.rules.len();
+        self.rules.retain(|rule| rule.id != id);
+        if self.rules.len() == count {
+            return Ok(false);
+        }
+        self.save()?;
+        Ok(true)
+    }
+
+    // Notifies the webhooks of the rules that started or stopped matching.
+    // State isn't changed if the webhook fails, so it's notified again next time.
+    pub async fn evaluate(
+        &self,
+        history: &History,
+        webhook_hosts: &[String],
+        now: DateTime<Utc>,
+    ) -> anyhow::Result<()> {
+        let mut changed_states: Vec<(&Rule, RuleState)> = vec![];
+        for rule in &self.rules {
+            let samples = history.samples(&rule.config.station_id);
+            let matching = rule.config.is_met(samples, now);
+            if matching == rule.state.matching {
+                continue;
+            }
+            if let Err(e) = rule.config.check_webhook_url(webhook_hosts) {
+                println!("[{}] {}", rule.id, e);
+                continue;
+            }
+
+            let latest = samples.last();
+            let event = Event {
+                rule_id: &rule.id,
+                station_id: &rule.config.station_id,
+                event: if matching { "started" } else { "stopped" },
+                time: now.format("%Y-%m-%dT%H:%MZ").to_string(),
+                wind_speed: latest.and_then(|sample| sample.wind_speed),
+                wind_direction: latest.and_then(|sample| sample.wind_direction),
+                gusts_speed: latest.and_then(|sample| sample.gusts_speed),
+            };
+            if let Err(e) = notify(&rule.config.webhook_url, &event).await {
+                println!("[{}] Webhook failed: {}", rule.id, e);
+                continue;
+            }
+            let state = RuleState {
+                matching,
+                since: Some(event.time),
+            };
+            changed_states.push((rule, state));
+        }
+        if changed_states.is_empty() {
+            return Ok(());
+        }
+
+        // Rules may have been changed meanwhile. State is kept only
+        // for the rules that still exist with the same conditions.
+        let mut rules = load()?;
+        for (evaluated_rule, state) in changed_states {
+            let rule = rules
+                .rules
+                .iter_mut()
+                .find(|rule| rule.id == evaluated_rule.id && rule.config == evaluated_rule.config);
+            if let Some(rule) = rule {
+                rule.state = state;
+            }
+        }
+        rules.save()
+    }
+}
+
+async fn notify(webhook_url: &str, event: &Event<'_>) -> anyhow::Result<()> {
+    let request = Request::builder()
+        .method(Method::Post)
+        .uri(webhook_url)
+        .header("content-type", "application/json")
+        .body(serde_json::to_vec(event)?)
+        .build();
+    let response: Response = spin_sdk::http::send(request).await?;
+    if !(200..300).contains(response.status()) {
+        anyhow::bail!("Unexpected status {}", response.status());
+    }
+    Ok(())
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-12T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn sample(minutes_ago: i64, wind_speed: u64, wind_direction: f64) -> Sample {
+        Sample {
+            last_update: (now() - Duration::minutes(minutes_ago))
+                .format("%Y-%m-%dT%H:%MZ")
+                .to_string(),
+            wind_speed: Some(wind_speed),
+            wind_direction: Some(wind_direction),
+            gusts_speed: None,
+            temperature: None,
+            humidity: None,
+            pressure: None,
+        }
+    }
+
+    // Direction 180-240°, speed 10-25 km/h, for 30 minutes.
+    fn config() -> RuleConfig {
+        RuleConfig {
+            station_id: "abc".to_owned(),
+            direction_min: Some(180.0),
+            direction_max: Some(240.0),
+            speed_min: Some(10.0),
+            speed_max: Some(25.0),
+            duration_minutes: 30,
+            webhook_url: "https://hooks.example.org/zephyr".to_owned(),
+        }
+    }
+
+    #[test]
+    fn matches_speed_thresholds() {
+        let config = config();
+        assert!(config.matches(&sample(0, 10, 200.0)));
+        assert!(config.matches(&sample(0, 25, 200.0)));
+        assert!(!config.matches(&sample(0, 9, 200.0)));
+        assert!(!config.matches(&sample(0, 26, 200.0)));
+    }
+
+    #[test]
+    fn matches_direction_range() {
+        let config = config();
+        assert!(config.matches(&sample(0, 15, 180.0)));
+        assert!(config.matches(&sample(0, 15, 240.0)));
+        assert!(!config.matches(&sample(0, 15, 250.0)));
+
+        let mut calm = sample(0, 15, 0.0);
+        calm.wind_direction = None;
+        assert!(!config.matches(&calm));
+
+        let config = RuleConfig {
+            direction_min: Some(300.0),
+            direction_max: Some(30.0),
+            ..config
+        };
+        assert!(config.matches(&sample(0, 15, 350.0)));
+        assert!(config.matches(&sample(0, 15, 10.0)));
+        assert!(!config.matches(&sample(0, 15, 200.0)));
+    }
+
+    #[test]
+    fn is_met_for_duration() {
+        let config = config();
+        let samples = [
+            sample(40, 15, 200.0),
+            sample(20, 18, 210.0),
+            sample(0, 20, 220.0),
+        ];
+        assert!(config.is_met(&samples, now()));
+        // Met for 20 minutes only.
+        let samples = [
+            sample(40, 5, 200.0),
+            sample(20, 18, 210.0),
+            sample(0, 20, 220.0),
+        ];
+        assert!(!config.is_met(&samples, now()));
+    }
+
+    #[test]
+    fn is_met_requires_recent_sample() {
+        let config = config();
+        let samples = [
+            sample(120, 15, 200.0),
+            sample(90, 18, 210.0),
+            sample(70, 20, 220.0),
+        ];
+        assert!(!config.is_met(&samples, now()));
+    }
+
+    #[test]
+    fn is_met_resets_on_gaps() {
+        let config = config();
+        let samples = [
+            sample(100, 15, 200.0),
+            sample(20, 18, 210.0),
+            sample(0, 20, 220.0),
+        ];
+        assert!(!config.is_met(&samples, now()));
+        // Hourly samples.
+        let samples = [sample(60, 15, 200.0), sample(0, 20, 220.0)];
+        assert!(config.is_met(&samples, now()));
+    }
+
+    #[test]
+    fn webhook_host_of_reads_host_of_https_urls() {
+        let host = webhook_host_of("https://Hooks.Example.org:8443/zephyr?x=1");
+        assert_eq!(host.as_deref(), Some("hooks.example.org"));
+        assert_eq!(webhook_host_of("http://hooks.example.org/zephyr"), None);
+    }
+
+    #[test]
+    fn webhook_host_of_rejects_credentials() {
+        assert_eq!(webhook_host_of("https://hooks.example.org@10.0.0.1/"), None);
+    }
+}
@@ -0,0 +1,112 @@
+use crate::common::{legacy_station_id_of, Station};
+
+use spin_sdk::key_value::Store;
+use std::collections::HashMap;
+
+const STORE_KEY: &str = "stations:aliases";
+
+// Legacy IDs of the stations mapped to the current ones. Legacy IDs of the
+// current stations are derived from their URLs, the ones of the stations
+// whose URLs changed since are kept in the default key-value store.
+#[derive(Default)]
+pub struct Aliases(HashMap<String, String>);
+
+pub fn load() -> anyhow::Result<Aliases> {
+    let store = Store::open_default()?;
+    let aliases = store
+        .get_json::<HashMap<String, String>>(STORE_KEY)?
+        .unwrap_or_default();
+    Ok(Aliases(aliases))
+}
+
+impl Aliases {
+    // Adds legacy IDs of the stations not known yet, returns how many were added.
+    pub fn add(&mut self, stations: &[Station]) -> usize {
+        let mut added_count = 0;
+        for station in stations {
+            let legacy_id = legacy_station_id_of(&station.url);
+            if self.0.get(&legacy_id) != Some(&station.id) {
+                self.0.insert(legacy_id, station.id.to_owned());
+                added_count += 1;
+            }
+        }
+        added_count
+    }
+
+    // Adds legacy IDs of the stations and stores them, so that they're still
+    // known once the URLs change.
+    pub fn record(&mut self, stations: &[Station]) -> anyhow::Result<()> {
+        let added_count = self.add(stations);
+        if added_count > 0 {
+            println!("Added {} station aliases", added_count);
+            Store::open_default()?.set_json(STORE_KEY, &self.0)?;
+        }
+        Ok(())
+    }
+
+    // Current ID of the station, legacy IDs are accepted too.
+    pub fn resolve<'a>(&'a self, id: &'a str) -> &'a str {
+        self.0.get(id).map(|id| id.as_str()).unwrap_or(id)
+    }
+
+    pub fn all(&self) -> &HashMap<String, String> {
+        &self.0
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::station_id_of;
+
+    fn station(vendor_id: &str, url: &str) -> Station {
+        Station {
+            id: station_id_of("meteocat", vendor_id),
+            name: vendor_id.to_owned(),
+            elevation: None,
+            url: url.to_owned(),
+            lat: 41.4,
+            long: 2.2,
+            available: true,
+            provider: "meteocat".to_owned(),
+            vendor_id: vendor_id.to_owned(),
+            alternates: vec![],
+        }
+    }
+
+    #[test]
+    fn resolve_legacy_ids_of_current_stations() {
+        let x4 = station(
+            "X4",
+            "https://www.meteo.cat/observacions/xema/dades?codi=X4",
+        );
+        let mut aliases = Aliases::default();
+        assert_eq!(aliases.add(std::slice::from_ref(&x4)), 1);
+
+        let legacy_id = legacy_station_id_of(&x4.url);
+        assert_eq!(aliases.resolve(&legacy_id), x4.id);
+        // Current and unknown IDs are kept as they are.
+        assert_eq!(aliases.resolve(&x4.id), x4.id);
+        assert_eq!(aliases.resolve("unknown"), "unknown");
+        // Known ones aren't added twice.
+        assert_eq!(aliases.add(&[x4]), 0);
+    }
+
+    #[test]
+    fn resolve_legacy_ids_of_former_urls() {
+        let old = station(
+            "X4",
+            "https://www.meteo.cat/observacions/xema/dades?codi=X4",
+        );
+        let new = station("X4", "https://www.meteo.cat/estacions/X4");
+        let old_legacy_id = legacy_station_id_of(&old.url);
+        // Recorded while the station had its former URL.
+        let mut aliases = Aliases::default();
+        aliases.add(&[old]);
+        aliases.add(std::slice::from_ref(&new));
+
+        assert_eq!(aliases.all().len(), 2);
+        assert_eq!(aliases.resolve(&old_legacy_id), new.id);
+        assert_eq!(aliases.resolve(&legacy_station_id_of(&new.url)), new.id);
+    }
+}
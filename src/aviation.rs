@@ -0,0 +1,180 @@
+use crate::common::{fetch_utf8, station_id_of, Measurement, Station};
+use crate::metar;
+
+use chrono::DateTime;
+use serde::Deserialize;
+
+const API_URL: &str = "https://aviationweather.gov/api/data/metar";
+const STATION_BASE_URL: &str = "https://aviationweather.gov/data/metar/";
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct ReportRaw {
+    icaoId: String,
+    name: Option<String>,
+    lat: f64,
+    lon: f64,
+    elev: Option<f64>,
+    obsTime: i64,
+    rawOb: String,
+}
+
+// ICAO codes of the airports.
+pub fn stations() -> anyhow::Result<Vec<String>> {
+    Ok(parse_stations(&spin_sdk::variables::get("metar_stations")?))
+}
+
+// Only METAR is supported. WMO numbers of SYNOP stations, e.g. 08181, are skipped.
+fn parse_stations(stations: &str) -> Vec<String> {
+    stations
+        .split(',')
+        .map(|station| station.trim().to_uppercase())
+        .filter(|station| !station.is_empty())
+        .filter(|station| {
+            let is_icao = station.len() == 4 && station.chars().all(|c| c.is_ascii_alphanumeric());
+            if !is_icao {
+                println!("[{}] Not an ICAO code, SYNOP isn't supported", station);
+            }
+            is_icao
+        })
+        .collect()
+}
+
+// SYNOP reports of land stations, ships and mobile stations.
+fn is_synop(report: &str) -> bool {
+    matches!(
+        report.split_whitespace().next(),
+        Some("AAXX" | "BBXX" | "OOXX")
+    )
+}
+
+pub async fn fetch_data(stations: &[String]) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+    println!("[aviation] Fetching...");
+    let url = format!("{}?ids={}&format=json", API_URL, stations.join(","));
+    let body = fetch_utf8(&url).await?;
+
+    println!("Analyzing...");
+    let reports: Vec<ReportRaw> = serde_json::from_str(&body)?;
+
+    let mut available_stations: Vec<Station> = vec![];
+    let mut measurements: Vec<Measurement> = vec![];
+
+    for report in reports {
+        match convert(report) {
+            Ok(Some((station, measurement))) => {
+                available_stations.push(station);
+                measurements.push(measurement);
+            }
+            Ok(None) => {}
+            Err(e) => println!("{}", e),
+        }
+    }
+
+    Ok((available_stations, measurements))
+}
+
+fn convert(report: ReportRaw) -> anyhow::Result<Option<(Station, Measurement)>> {
+    if is_synop(&report.rawOb) {
+        println!(
+            "[{}] SYNOP report skipped, it isn't supported",
+            report.icaoId
+        );
+        return Ok(None);
+    }
+    let decoded =
+        metar::decode(&report.rawOb).map_err(|e| anyhow::anyhow!("[{}]: {}", report.icaoId, e))?;
+    let Some(wind) = decoded.wind else {
+        println!("[{}] Wind information not available", report.icaoId);
+        return Ok(None);
+    };
+
+    // Time of the observation with the date included.
+    let last_update = DateTime::from_timestamp(report.obsTime, 0)
+        .ok_or_else(|| anyhow::anyhow!("[{}]: Invalid time {}", report.icaoId, report.obsTime))?
+        .format("%Y-%m-%dT%H:%MZ")
+        .to_string();
+
+    let station_url = format!("{}?ids={}", STATION_BASE_URL, report.icaoId);
+    let station_id = station_id_of("aviation", &report.icaoId);
+
+    let measurement = Measurement {
+        station_id: station_id.clone(),
+        wind_speed: wind.speed_kmh().round() as u64,
+        wind_direction: wind.direction,
+        gusts_speed: wind.gust_kmh().map(|v| v.round() as u64),
+        temperature: decoded.temperature,
+        pressure: decoded.pressure,
+        last_update,
+        ..Default::default()
+    };
+    let station = Station {
+        id: station_id,
+        name: report.name.unwrap_or_else(|| report.icaoId.to_owned()),
+        elevation: report.elev.map(|elev| elev.round() as i64),
+        url: station_url,
+        lat: report.lat,
+        long: report.lon,
+        available: true,
+        provider: "aviation".to_owned(),
+        vendor_id: report.icaoId.to_owned(),
+        alternates: vec![],
+    };
+    Ok(Some((station, measurement)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn reports() -> Vec<ReportRaw> {
+        serde_json::from_str(include_str!("../tests/fixtures/aviation_metar.json")).unwrap()
+    }
+
+    #[test]
+    fn convert_of_metar() {
+        let (station, measurement) = convert(reports().remove(0)).unwrap().unwrap();
+
+        assert_eq!(station.id, station_id_of("aviation", "LELL"));
+        assert_eq!(station.name, "Sabadell Arpt, CT, ES");
+        assert_eq!(
+            station.url,
+            "https://aviationweather.gov/data/metar/?ids=LELL"
+        );
+        assert_eq!(station.elevation, Some(148));
+        assert_eq!((station.lat, station.long), (41.521, 2.105));
+
+        // 1715632200
+        assert_eq!(measurement.last_update, "2024-05-13T20:30Z");
+        // 15 kt and 25 kt
+        assert_eq!(measurement.wind_speed, 28);
+        assert_eq!(measurement.gusts_speed, Some(46));
+        assert_eq!(measurement.wind_direction, Some(240.0));
+        assert_eq!(measurement.temperature, Some(21.0));
+        assert_eq!(measurement.pressure, Some(1013.0));
+    }
+
+    #[test]
+    fn convert_of_variable_wind() {
+        let (station, measurement) = convert(reports().remove(1)).unwrap().unwrap();
+        // Name defaults to the ICAO code.
+        assert_eq!(station.name, "LEGE");
+        assert_eq!(measurement.wind_speed, 6);
+        assert_eq!(measurement.wind_direction, None);
+        assert_eq!(measurement.temperature, Some(-2.0));
+    }
+
+    #[test]
+    fn convert_skips_reports_without_wind_and_synop() {
+        let mut reports = reports();
+        assert!(convert(reports.remove(3)).unwrap().is_none());
+        assert!(convert(reports.remove(2)).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_stations_skips_synop_stations() {
+        assert_eq!(
+            parse_stations(" lebl, LEGE,08181,,LELL "),
+            ["LEBL", "LEGE", "LELL"]
+        );
+    }
+}
@@ -0,0 +1,256 @@
+use crate::common::distance_m;
+use std::f64::consts::PI;
+
+// Known geolocation of a point found at (x, y) on a map image.
+#[derive(Debug, Clone, Copy)]
+pub struct ReferencePoint {
+    pub x: f64,
+    pub y: f64,
+    pub lat: f64,
+    pub long: f64,
+}
+
+// Maps pixel location (x, y) to Web Mercator coordinates with an affine
+// transform, so that scaling, shearing and rotation of the map are accounted for.
+#[derive(Debug, Clone, Copy)]
+pub struct Calibration {
+    mx: [f64; 3],
+    my: [f64; 3],
+    pub points_count: usize,
+    pub rms_error_m: f64,
+    pub max_error_m: f64,
+}
+
+impl Calibration {
+    // Least squares fit of the transform. With only two reference points
+    // rotation can't be determined, so axes are assumed to be aligned.
+    pub fn fit(points: &[ReferencePoint]) -> anyhow::Result<Calibration> {
+        if points.len() < 2 {
+            anyhow::bail!(
+                "At least 2 reference points required, {} given",
+                points.len()
+            );
+        }
+
+        let affine = if points.len() >= 3 {
+            fit_affine(points)
+        } else {
+            None
+        };
+
+        let (mx, my) = match affine {
+            Some(affine) => affine,
+            None => fit_aligned(points).ok_or_else(|| {
+                anyhow::anyhow!("Reference points don't span the map in both directions")
+            })?,
+        };
+
+        let mut calibration = Calibration {
+            mx,
+            my,
+            points_count: points.len(),
+            rms_error_m: 0.0,
+            max_error_m: 0.0,
+        };
+
+        let errors = points
+            .iter()
+            .map(|p| calibration.error_m(p))
+            .collect::<Vec<f64>>();
+        calibration.rms_error_m =
+            (errors.iter().map(|e| e * e).sum::<f64>() / errors.len() as f64).sqrt();
+        calibration.max_error_m = errors.iter().cloned().fold(0.0, f64::max);
+
+        Ok(calibration)
+    }
+
+    // Distance between the known location of the point and the converted one.
+    pub fn error_m(&self, point: &ReferencePoint) -> f64 {
+        let (long, lat) = self.xy_to_long_lat(point.x, point.y);
+        distance_m(lat, long, point.lat, point.long)
+    }
+
+    pub fn xy_to_long_lat(&self, x: f64, y: f64) -> (f64, f64) {
+        let mx = self.mx[0] + self.mx[1] * x + self.mx[2] * y;
+        let my = self.my[0] + self.my[1] * x + self.my[2] * y;
+        mercator_to_long_lat(mx, my)
+    }
+}
+
+fn long_lat_to_mercator(long: f64, lat: f64) -> (f64, f64) {
+    let mx = long.to_radians();
+    let my = (PI / 4.0 + lat.to_radians() / 2.0).tan().ln();
+    (mx, my)
+}
+
+fn mercator_to_long_lat(mx: f64, my: f64) -> (f64, f64) {
+    let long = mx.to_degrees();
+    let lat = (2.0 * my.exp().atan() - PI / 2.0).to_degrees();
+    (long, lat)
+}
+
+type Coefficients = ([f64; 3], [f64; 3]);
+
+fn fit_affine(points: &[ReferencePoint]) -> Option<Coefficients> {
+    // Normal equations: (A^T A) c = A^T b, where rows of A are [1, x, y].
+    let mut ata = [[0.0; 3]; 3];
+    let mut atb_x = [0.0; 3];
+    let mut atb_y = [0.0; 3];
+    for p in points {
+        let row = [1.0, p.x, p.y];
+        let (mx, my) = long_lat_to_mercator(p.long, p.lat);
+        for i in 0..3 {
+            for j in 0..3 {
+                ata[i][j] += row[i] * row[j];
+            }
+            atb_x[i] += row[i] * mx;
+            atb_y[i] += row[i] * my;
+        }
+    }
+    Some((solve3(ata, atb_x)?, solve3(ata, atb_y)?))
+}
+
+fn fit_aligned(points: &[ReferencePoint]) -> Option<Coefficients> {
+    let xs = points.iter().map(|p| p.x).collect::<Vec<f64>>();
+    let ys = points.iter().map(|p| p.y).collect::<Vec<f64>>();
+    let (mxs, mys): (Vec<f64>, Vec<f64>) = points
+        .iter()
+        .map(|p| long_lat_to_mercator(p.long, p.lat))
+        .unzip();
+    let (cx, kx) = fit_line(&xs, &mxs)?;
+    let (cy, ky) = fit_line(&ys, &mys)?;
+    Some(([cx, kx, 0.0], [cy, 0.0, ky]))
+}
+
+// Returns (intercept, slope) of the least squares line.
+fn fit_line(args: &[f64], values: &[f64]) -> Option<(f64, f64)> {
+    let n = args.len() as f64;
+    let mean_arg = args.iter().sum::<f64>() / n;
+    let mean_value = values.iter().sum::<f64>() / n;
+    let mut cov = 0.0;
+    let mut var = 0.0;
+    for (arg, value) in args.iter().zip(values) {
+        cov += (arg - mean_arg) * (value - mean_value);
+        var += (arg - mean_arg).powi(2);
+    }
+    if var.abs() < f64::EPSILON {
+        return None;
+    }
+    let slope = cov / var;
+    Some((mean_value - slope * mean_arg, slope))
+}
+
+// Gaussian elimination with partial pivoting.
+fn solve3(mut m: [[f64; 3]; 3], mut v: [f64; 3]) -> Option<[f64; 3]> {
+    for col in 0..3 {
+        let pivot = (col..3).max_by(|&a, &b| m[a][col].abs().total_cmp(&m[b][col].abs()))?;
+        if m[pivot][col].abs() < 1e-9 {
+            return None;
+        }
+        m.swap(col, pivot);
+        v.swap(col, pivot);
+        for row in col + 1..3 {
+            let factor = m[row][col] / m[col][col];
+            let pivot_row = m[col];
+            for (k, cell) in m[row].iter_mut().enumerate().skip(col) {
+                *cell -= factor * pivot_row[k];
+            }
+            v[row] -= factor * v[col];
+        }
+    }
+    let mut result = [0.0; 3];
+    for row in (0..3).rev() {
+        let tail = (row + 1..3).map(|k| m[row][k] * result[k]).sum::<f64>();
+        result[row] = (v[row] - tail) / m[row][row];
+    }
+    Some(result)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Map rotated by 10 degrees, with y growing southwards like on images.
+    fn rotated_point(x: f64, y: f64) -> ReferencePoint {
+        let (sin, cos) = 10f64.to_radians().sin_cos();
+        let scale = 1e-5;
+        let mx = 0.03 + scale * (cos * x - sin * y);
+        let my = 0.75 - scale * (sin * x + cos * y);
+        let (long, lat) = mercator_to_long_lat(mx, my);
+        ReferencePoint { x, y, lat, long }
+    }
+
+    fn rotated_grid() -> Vec<ReferencePoint> {
+        let mut points = vec![];
+        for x in [0.0, 250.0, 500.0] {
+            for y in [0.0, 200.0, 400.0] {
+                points.push(rotated_point(x, y));
+            }
+        }
+        points
+    }
+
+    #[test]
+    fn solve3_solves_system() {
+        let m = [[2.0, 1.0, -1.0], [-3.0, -1.0, 2.0], [-2.0, 1.0, 2.0]];
+        let result = solve3(m, [8.0, -11.0, -3.0]).unwrap();
+        for (actual, expected) in result.iter().zip([2.0, 3.0, -1.0]) {
+            assert!((actual - expected).abs() < 1e-9);
+        }
+    }
+
+    #[test]
+    fn solve3_needs_pivoting() {
+        let m = [[0.0, 1.0, 0.0], [1.0, 0.0, 0.0], [0.0, 0.0, 1.0]];
+        assert_eq!(solve3(m, [1.0, 2.0, 3.0]), Some([2.0, 1.0, 3.0]));
+    }
+
+    #[test]
+    fn solve3_rejects_singular_matrix() {
+        let m = [[1.0, 2.0, 3.0], [2.0, 4.0, 6.0], [1.0, 0.0, 1.0]];
+        assert_eq!(solve3(m, [1.0, 2.0, 3.0]), None);
+    }
+
+    #[test]
+    fn fit_affine_recovers_rotated_grid() {
+        let (mx, my) = fit_affine(&rotated_grid()).unwrap();
+        let (sin, cos) = 10f64.to_radians().sin_cos();
+        let expected_mx = [0.03, 1e-5 * cos, -1e-5 * sin];
+        let expected_my = [0.75, -1e-5 * sin, -1e-5 * cos];
+        for (actual, expected) in mx
+            .iter()
+            .chain(&my)
+            .zip(expected_mx.iter().chain(&expected_my))
+        {
+            assert!(
+                (actual - expected).abs() < 1e-9,
+                "{} != {}",
+                actual,
+                expected
+            );
+        }
+    }
+
+    #[test]
+    fn fit_locates_points_off_the_grid() {
+        let calibration = Calibration::fit(&rotated_grid()).unwrap();
+        assert_eq!(calibration.points_count, 9);
+        assert!(calibration.max_error_m < 1.0);
+
+        let expected = rotated_point(123.0, 321.0);
+        let (long, lat) = calibration.xy_to_long_lat(123.0, 321.0);
+        assert!(distance_m(lat, long, expected.lat, expected.long) < 1.0);
+    }
+
+    #[test]
+    fn fit_assumes_aligned_axes_for_collinear_points() {
+        let points = [
+            rotated_point(0.0, 0.0),
+            rotated_point(100.0, 100.0),
+            rotated_point(200.0, 200.0),
+        ];
+        let calibration = Calibration::fit(&points).unwrap();
+        assert_eq!(calibration.mx[2], 0.0);
+        assert_eq!(calibration.my[1], 0.0);
+    }
+}
@@ -1,13 +1,20 @@
 use anyhow::anyhow;
+use chrono::{DateTime, NaiveDateTime, Utc};
+use encoding_rs::UTF_8;
 use scraper::Selector;
 use serde::Serialize;
-use std::collections::HashMap;
+use spin_sdk::http::{Method, Request, Response};
+use std::collections::{BTreeMap, HashMap};
+
+const EARTH_RADIUS_M: f64 = 6_371_000.0;
 
 #[derive(Debug, Clone, Serialize)]
 pub struct Station {
     pub id: String,
     pub name: String,
-    pub elevation: i64,
+    // None if the provider doesn't report it. Reported as 0 by the API.
+    #[serde(serialize_with = "serialize_elevation")]
+    pub elevation: Option<i64>,
     pub url: String,
     pub lat: f64,
     pub long: f64,
@@ -16,6 +23,25 @@ pub struct Station {
     // speed and direction are available. Wind gusts are optional.
     #[serde(skip_serializing)]
     pub available: bool,
+
+    // Data source of the station, e.g. "meteocat".
+    #[serde(skip_serializing)]
+    pub provider: String,
+
+    // ID of the station in the data source, e.g. "X4" in Meteocat.
+    #[serde(skip_serializing)]
+    pub vendor_id: String,
+
+    // IDs of the same station reported by other providers.
+    #[serde(skip_serializing_if = "Vec::is_empty")]
+    pub alternates: Vec<String>,
+}
+
+fn serialize_elevation<S: serde::Serializer>(
+    elevation: &Option<i64>,
+    serializer: S,
+) -> Result<S::Ok, S::Error> {
+    serializer.serialize_i64(elevation.unwrap_or(0))
 }
 
 #[derive(Debug, Default, Serialize)]
@@ -24,14 +50,178 @@ pub struct Measurement {
     pub wind_speed: u64,
     pub wind_direction: Option<f64>,
     pub gusts_speed: Option<u64>,
+    pub gusts_direction: Option<f64>,
+    pub wind_sensor_height: Option<f64>,
+    pub daily_max_wind: Option<u64>,
+    pub daily_max_wind_since: Option<String>,
     pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub pressure: Option<f64>,
+    pub precipitation: Option<f64>,
+    pub irradiance: Option<f64>,
     pub last_update: String,
+
+    // Marks measurements older than allowed, when they are requested anyway.
+    #[serde(skip_serializing_if = "std::ops::Not::not")]
+    pub stale: bool,
+
+    // Fields whose values failed the quality control, with the reason.
+    #[serde(skip_serializing_if = "BTreeMap::is_empty")]
+    pub qc_flags: BTreeMap<&'static str, QcFlag>,
+
+    // Added on request only.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub wind_details: Option<WindDetails>,
+
+    // Added on request only.
+    #[serde(flatten, skip_serializing_if = "Option::is_none")]
+    pub derived: Option<DerivedValues>,
+}
+
+#[derive(Debug, Clone, Serialize)]
+pub struct WindDetails {
+    // One of the 16 compass points, e.g. "NNE".
+    pub wind_direction_label: Option<&'static str>,
+    pub beaufort: u8,
+    // E.g. "Brisa feble del nord-est".
+    pub wind_description: String,
+    // Vector average of the directions measured in the last hour, weighted by speeds.
+    pub wind_direction_1h: Option<f64>,
+}
+
+// See `derived` for the formulas.
+#[derive(Debug, Clone, Serialize)]
+pub struct DerivedValues {
+    pub gust_factor: Option<f64>,
+    pub wind_chill: Option<f64>,
+    // Circular standard deviation of the directions measured in the last hour.
+    pub wind_direction_spread: Option<f64>,
+}
+
+#[derive(Debug, Clone, Copy, PartialEq, Serialize)]
+#[serde(rename_all = "snake_case")]
+pub enum QcFlag {
+    // Value outside of the physically plausible range.
+    OutOfRange,
+    // Gusts slower than the mean wind.
+    GustBelowWind,
+    // Value far from the ones of the nearby stations.
+    SpatialOutlier,
+    // Value changed too much since the previous measurement.
+    StepChange,
+}
+
+// Area of interest, used to pick stations from nationwide networks.
+#[derive(Debug, Clone, Copy)]
+pub struct BoundingBox {
+    pub min_lat: f64,
+    pub min_long: f64,
+    pub max_lat: f64,
+    pub max_long: f64,
+}
+
+impl BoundingBox {
+    // Parses "min_lat,min_long,max_lat,max_long".
+    pub fn parse(bbox: &str) -> anyhow::Result<BoundingBox> {
+        let values = bbox
+            .split(',')
+            .map(|v| v.trim().parse::<f64>())
+            .collect::<Result<Vec<f64>, _>>()
+            .map_err(|e| anyhow!("Invalid bounding box '{}': {}", bbox, e))?;
+        if let [min_lat, min_long, max_lat, max_long] = values[..] {
+            Ok(BoundingBox {
+                min_lat,
+                min_long,
+                max_lat,
+                max_long,
+            })
+        } else {
+            anyhow::bail!("Invalid bounding box '{}': 4 values expected", bbox);
+        }
+    }
+
+    pub fn contains(&self, lat: f64, long: f64) -> bool {
+        (self.min_lat..=self.max_lat).contains(&lat)
+            && (self.min_long..=self.max_long).contains(&long)
+    }
+}
+
+// GET request to a service that replies with UTF-8 encoded text.
+pub async fn fetch_utf8(url: &str) -> anyhow::Result<String> {
+    let request = Request::builder().method(Method::Get).uri(url).build();
+    let response: Response = spin_sdk::http::send(request).await?;
+    if *response.status() != 200 {
+        anyhow::bail!("Unexpected status {}", response.status());
+    }
+    let (body, _, _) = UTF_8.decode(response.body());
+    Ok(body.into_owned())
+}
+
+// GET request with an extra header, e.g. of the authentication.
+pub async fn fetch_utf8_with_header(url: &str, name: &str, value: &str) -> anyhow::Result<String> {
+    let request = Request::builder()
+        .method(Method::Get)
+        .uri(url)
+        .header(name, value)
+        .build();
+    let response: Response = spin_sdk::http::send(request).await?;
+    if *response.status() != 200 {
+        anyhow::bail!("Unexpected status {}", response.status());
+    }
+    let (body, _, _) = UTF_8.decode(response.body());
+    Ok(body.into_owned())
+}
+
+// IDs don't depend on the URLs, which may change when the data sources are redesigned.
+pub fn station_id_of(provider: &str, vendor_id: &str) -> String {
+    format!("{:x}", md5::compute(format!("{}:{}", provider, vendor_id)))
+}
+
+// IDs used to be MD5 of the station URL.
+pub fn legacy_station_id_of(url: &str) -> String {
+    format!("{:x}", md5::compute(url))
+}
+
+// Parses `Measurement::last_update`, minutes and seconds precision is accepted.
+pub fn parse_last_update(last_update: &str) -> Option<DateTime<Utc>> {
+    NaiveDateTime::parse_from_str(last_update, "%Y-%m-%dT%H:%MZ")
+        .map(|date_time| date_time.and_utc())
+        .or_else(|_| DateTime::parse_from_rfc3339(last_update).map(|d| d.with_timezone(&Utc)))
+        .ok()
 }
 
 pub fn parse_selector(selector: &str) -> anyhow::Result<Selector> {
     Selector::parse(selector).map_err(|e| anyhow!(e.to_string()))
 }
 
+pub fn ms_to_kmh(speed: f64) -> f64 {
+    speed * 3.6
+}
+
+pub fn knots_to_kmh(speed: f64) -> f64 {
+    speed * 1.852
+}
+
+pub fn mph_to_kmh(speed: f64) -> f64 {
+    speed * 1.609344
+}
+
+pub fn fahrenheit_to_celsius(temperature: f64) -> f64 {
+    (temperature - 32.0) * 5.0 / 9.0
+}
+
+pub fn inhg_to_hpa(pressure: f64) -> f64 {
+    pressure * 33.863886
+}
+
+pub fn inches_to_mm(length: f64) -> f64 {
+    length * 25.4
+}
+
+pub fn feet_to_m(length: f64) -> f64 {
+    length * 0.3048
+}
+
 pub fn wind_direction_to_degrees(direction: &str) -> Option<f64> {
     match direction.to_uppercase().as_str() {
         "N" => Some(0.0),
@@ -54,12 +244,32 @@ pub fn wind_direction_to_degrees(direction: &str) -> Option<f64> {
     }
 }
 
+// Great-circle distance between two geolocations.
+pub fn distance_m(lat1: f64, long1: f64, lat2: f64, long2: f64) -> f64 {
+    let (lat1, lat2) = (lat1.to_radians(), lat2.to_radians());
+    let dlat = lat2 - lat1;
+    let dlong = (long2 - long1).to_radians();
+    let a = (dlat / 2.0).sin().powi(2) + lat1.cos() * lat2.cos() * (dlong / 2.0).sin().powi(2);
+    2.0 * EARTH_RADIUS_M * a.sqrt().asin()
+}
+
 pub fn get_units() -> HashMap<&'static str, &'static str> {
     let units: HashMap<&str, &str> = HashMap::from([
         ("wind_speed", "km/h"),
         ("wind_direction", "°"),
         ("gusts_speed", "km/h"),
+        ("gusts_direction", "°"),
+        ("wind_sensor_height", "m"),
+        ("daily_max_wind", "km/h"),
+        ("daily_max_wind_since", "ISO 8601"),
         ("temperature", "°C"),
+        ("humidity", "%"),
+        ("pressure", "hPa"),
+        ("precipitation", "mm"),
+        ("irradiance", "W/m²"),
+        ("wind_direction_1h", "°"),
+        ("wind_chill", "°C"),
+        ("wind_direction_spread", "°"),
         ("lat", "°"),
         ("long", "°"),
         ("elevation", "m"),
@@ -0,0 +1,324 @@
+use crate::common::{
+    fahrenheit_to_celsius, feet_to_m, fetch_utf8, inches_to_mm, inhg_to_hpa, knots_to_kmh,
+    mph_to_kmh, ms_to_kmh, station_id_of, wind_direction_to_degrees, Measurement, Station,
+};
+
+use chrono::{DateTime, NaiveDateTime, Utc};
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+// Source of station data described entirely by configuration, e.g.
+//
+// {
+//   "name": "club",
+//   "url": "https://example.org/stations.json",
+//   "format": "json",
+//   "records": "/stations",
+//   "fields": {
+//     "vendor_id": { "source": "/id" },
+//     "name": { "source": "/name" },
+//     "lat": { "source": "/lat" },
+//     "long": { "source": "/lon" },
+//     "elevation": { "source": "/alt", "unit": "ft" },
+//     "wind_speed": { "source": "/wind/avg", "unit": "m/s" },
+//     "wind_direction": { "source": "/wind/dir" },
+//     "last_update": { "source": "/time", "time_format": "%Y-%m-%d %H:%M:%S" }
+//   }
+// }
+//
+// For JSON, sources are JSON pointers (RFC 6901) relative to the record.
+// For CSV, sources are column names from the header row.
+#[derive(Debug, Deserialize)]
+pub struct SourceConfig {
+    pub name: String,
+    url: String,
+    format: Format,
+    // JSON pointer to the list of records, the document itself by default.
+    #[serde(default)]
+    records: String,
+    #[serde(default = "default_delimiter")]
+    delimiter: char,
+    // Link to the station, "{vendor_id}" is replaced by the ID of the station.
+    station_url: Option<String>,
+    fields: FieldsConfig,
+}
+
+fn default_delimiter() -> char {
+    ','
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(rename_all = "lowercase")]
+enum Format {
+    Json,
+    Csv,
+}
+
+#[derive(Debug, Deserialize)]
+struct FieldsConfig {
+    vendor_id: FieldConfig,
+    name: FieldConfig,
+    lat: FieldConfig,
+    long: FieldConfig,
+    elevation: Option<FieldConfig>,
+    wind_speed: FieldConfig,
+    wind_direction: Option<FieldConfig>,
+    gusts_speed: Option<FieldConfig>,
+    temperature: Option<FieldConfig>,
+    humidity: Option<FieldConfig>,
+    pressure: Option<FieldConfig>,
+    precipitation: Option<FieldConfig>,
+    last_update: FieldConfig,
+}
+
+#[derive(Debug, Deserialize)]
+struct FieldConfig {
+    source: String,
+    unit: Option<String>,
+    // Format of the timestamps, RFC 3339 by default. "unix" for seconds since epoch.
+    time_format: Option<String>,
+}
+
+pub fn config() -> anyhow::Result<Vec<SourceConfig>> {
+    let config = spin_sdk::variables::get("custom_sources")?;
+    if config.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    Ok(serde_json::from_str(&config)?)
+}
+
+// Single record of the source, regardless of the format.
+trait Record {
+    fn get(&self, source: &str) -> Option<Value>;
+}
+
+impl Record for &Value {
+    fn get(&self, source: &str) -> Option<Value> {
+        self.pointer(source).filter(|v| !v.is_null()).cloned()
+    }
+}
+
+impl Record for HashMap<&str, &str> {
+    fn get(&self, source: &str) -> Option<Value> {
+        self.get(source)
+            .filter(|v| !v.is_empty())
+            .map(|v| Value::String(v.to_string()))
+    }
+}
+
+pub async fn fetch_data(config: &SourceConfig) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+    println!("[{}] Fetching...", config.name);
+    let body = fetch_utf8(&config.url).await?;
+
+    println!("Analyzing...");
+    let mut available_stations: Vec<Station> = vec![];
+    let mut measurements: Vec<Measurement> = vec![];
+    let mut consume = |record: &dyn Record| match convert(config, record) {
+        Ok((station, measurement)) => {
+            available_stations.push(station);
+            measurements.push(measurement);
+        }
+        Err(e) => println!("[{}] {}", config.name, e),
+    };
+
+    match config.format {
+        Format::Json => {
+            let document: Value = serde_json::from_str(&body)?;
+            let records = document
+                .pointer(&config.records)
+                .and_then(|records| records.as_array())
+                .ok_or_else(|| anyhow::anyhow!("No list of records at '{}'", config.records))?;
+            for record in records {
+                consume(&record);
+            }
+        }
+        Format::Csv => {
+            let mut lines = body.lines().filter(|line| !line.trim().is_empty());
+            let header = lines
+                .next()
+                .map(|line| split_csv_line(line, config.delimiter))
+                .ok_or_else(|| anyhow::anyhow!("No header found"))?;
+            for line in lines {
+                let cells = split_csv_line(line, config.delimiter);
+                let record: HashMap<&str, &str> = header
+                    .iter()
+                    .map(|column| column.as_str())
+                    .zip(cells.iter().map(|cell| cell.as_str()))
+                    .collect();
+                consume(&record);
+            }
+        }
+    }
+
+    Ok((available_stations, measurements))
+}
+
+// Quoted cells may contain the delimiter, quotes in them are doubled.
+// Line breaks in quoted cells aren't supported.
+fn split_csv_line(line: &str, delimiter: char) -> Vec<String> {
+    let mut cells = vec![];
+    let mut cell = String::new();
+    let mut quoted = false;
+    let mut chars = line.chars().peekable();
+    while let Some(c) = chars.next() {
+        match c {
+            '"' if quoted && chars.peek() == Some(&'"') => {
+                cell.push('"');
+                chars.next();
+            }
+            '"' => quoted = !quoted,
+            c if c == delimiter && !quoted => {
+                cells.push(cell.trim().to_owned());
+                cell.clear();
+            }
+            c => cell.push(c),
+        }
+    }
+    cells.push(cell.trim().to_owned());
+    cells
+}
+
+fn convert(config: &SourceConfig, record: &dyn Record) -> anyhow::Result<(Station, Measurement)> {
+    let fields = &config.fields;
+    let vendor_id = match record.get(&fields.vendor_id.source) {
+        Some(Value::String(vendor_id)) => vendor_id,
+        Some(vendor_id) => vendor_id.to_string(),
+        None => anyhow::bail!("No station ID found"),
+    };
+
+    let number = |field: &FieldConfig| -> anyhow::Result<f64> {
+        let value = record
+            .get(&field.source)
+            .ok_or_else(|| anyhow::anyhow!("[{}] No value of '{}'", vendor_id, field.source))?;
+        let value = to_number(&value).ok_or_else(|| {
+            anyhow::anyhow!(
+                "[{}] Invalid value of '{}': {}",
+                vendor_id,
+                field.source,
+                value
+            )
+        })?;
+        convert_unit(value, field.unit.as_deref())
+    };
+    let optional_number = |field: &Option<FieldConfig>| -> Option<f64> {
+        field.as_ref().and_then(|field| number(field).ok())
+    };
+
+    let wind_speed = number(&fields.wind_speed)?.round() as u64;
+    let wind_direction = fields
+        .wind_direction
+        .as_ref()
+        .and_then(|field| record.get(&field.source))
+        .and_then(|value| match (to_number(&value), value) {
+            (Some(direction), _) => Some(direction),
+            (None, Value::String(direction)) => wind_direction_to_degrees(&direction),
+            _ => None,
+        })
+        .filter(|_| wind_speed != 0);
+
+    let last_update = record
+        .get(&fields.last_update.source)
+        .and_then(|value| parse_time(&value, fields.last_update.time_format.as_deref()))
+        .ok_or_else(|| anyhow::anyhow!("[{}] Invalid time", vendor_id))?
+        .format("%Y-%m-%dT%H:%MZ")
+        .to_string();
+
+    let station_url = match &config.station_url {
+        Some(station_url) => station_url.replace("{vendor_id}", &vendor_id),
+        None => format!("{}#{}", config.url, vendor_id),
+    };
+    let station_id = station_id_of(&format!("custom/{}", config.name), &vendor_id);
+
+    let measurement = Measurement {
+        station_id: station_id.clone(),
+        wind_speed,
+        wind_direction,
+        gusts_speed: optional_number(&fields.gusts_speed).map(|v| v.round() as u64),
+        temperature: optional_number(&fields.temperature),
+        humidity: optional_number(&fields.humidity),
+        pressure: optional_number(&fields.pressure),
+        precipitation: optional_number(&fields.precipitation),
+        last_update,
+        ..Default::default()
+    };
+    let name = match record.get(&fields.name.source) {
+        Some(Value::String(name)) => name,
+        _ => vendor_id.to_owned(),
+    };
+    let station = Station {
+        id: station_id,
+        name,
+        elevation: optional_number(&fields.elevation).map(|elevation| elevation.round() as i64),
+        url: station_url,
+        lat: number(&fields.lat)?,
+        long: number(&fields.long)?,
+        available: true,
+        provider: format!("custom/{}", config.name),
+        vendor_id: vendor_id.to_owned(),
+        alternates: vec![],
+    };
+    Ok((station, measurement))
+}
+
+fn to_number(value: &Value) -> Option<f64> {
+    match value {
+        Value::Number(number) => number.as_f64(),
+        Value::String(number) => number.trim().replace(',', ".").parse::<f64>().ok(),
+        _ => None,
+    }
+}
+
+// Converts to the units of the service: km/h, °C, hPa, mm and m.
+fn convert_unit(value: f64, unit: Option<&str>) -> anyhow::Result<f64> {
+    let converted = match unit {
+        None | Some("km/h") | Some("°C") | Some("C") | Some("hPa") | Some("mm") | Some("m")
+        | Some("°") => value,
+        Some("m/s") => ms_to_kmh(value),
+        Some("mph") => mph_to_kmh(value),
+        Some("kt") | Some("knots") => knots_to_kmh(value),
+        Some("°F") | Some("F") => fahrenheit_to_celsius(value),
+        Some("inHg") => inhg_to_hpa(value),
+        Some("in") => inches_to_mm(value),
+        Some("ft") => feet_to_m(value),
+        Some(unit) => anyhow::bail!("Unsupported unit '{}'", unit),
+    };
+    Ok(converted)
+}
+
+fn parse_time(value: &Value, time_format: Option<&str>) -> Option<DateTime<Utc>> {
+    match (value, time_format) {
+        (value, Some("unix")) => DateTime::from_timestamp(to_number(value)? as i64, 0),
+        (Value::String(time), Some(time_format)) => {
+            NaiveDateTime::parse_from_str(time, time_format)
+                .ok()
+                .map(|time| time.and_utc())
+        }
+        (Value::String(time), None) => DateTime::parse_from_rfc3339(time)
+            .ok()
+            .map(|time| time.with_timezone(&Utc)),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn split_csv_line_keeps_quoted_delimiters() {
+        let cells = split_csv_line(r#"id1,"Coll de Nargó, mirador",12.5"#, ',');
+        assert_eq!(cells, vec!["id1", "Coll de Nargó, mirador", "12.5"]);
+    }
+
+    #[test]
+    fn split_csv_line_unescapes_doubled_quotes() {
+        let cells = split_csv_line(r#""El ""Cap"" de Creus"; 3"#, ';');
+        assert_eq!(cells, vec![r#"El "Cap" de Creus"#, "3"]);
+    }
+
+    #[test]
+    fn split_csv_line_keeps_empty_cells() {
+        assert_eq!(split_csv_line("a,,c,", ','), vec!["a", "", "c", ""]);
+    }
+}
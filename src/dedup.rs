@@ -0,0 +1,183 @@
+use crate::common::{distance_m, Measurement, Station};
+
+use std::collections::HashSet;
+
+// Stations of different providers closer than that are considered the same site.
+pub struct DedupConfig {
+    pub max_distance_m: f64,
+    pub max_elevation_diff_m: i64,
+    // Preferred providers first. Providers not listed come last.
+    pub provider_priority: Vec<String>,
+}
+
+pub fn config() -> anyhow::Result<DedupConfig> {
+    let max_distance_m = spin_sdk::variables::get("dedup_distance_m")?
+        .trim()
+        .parse()?;
+    let max_elevation_diff_m = spin_sdk::variables::get("dedup_elevation_m")?
+        .trim()
+        .parse()?;
+    let provider_priority = spin_sdk::variables::get("provider_priority")?
+        .split(',')
+        .map(|provider| provider.trim().to_owned())
+        .filter(|provider| !provider.is_empty())
+        .collect();
+    Ok(DedupConfig {
+        max_distance_m,
+        max_elevation_diff_m,
+        provider_priority,
+    })
+}
+
+impl DedupConfig {
+    // Custom sources are prioritized all together as "custom".
+    fn priority(&self, provider: &str) -> usize {
+        let provider = provider.split('/').next().unwrap_or(provider);
+        self.provider_priority
+            .iter()
+            .position(|p| p == provider)
+            .unwrap_or(self.provider_priority.len())
+    }
+
+    // Elevation is only compared when both stations report it.
+    fn is_same_site(&self, a: &Station, b: &Station) -> bool {
+        let elevation_matches = match (a.elevation, b.elevation) {
+            (Some(a), Some(b)) => (a - b).abs() <= self.max_elevation_diff_m,
+            _ => true,
+        };
+        a.provider != b.provider
+            && elevation_matches
+            && distance_m(a.lat, a.long, b.lat, b.long) <= self.max_distance_m
+    }
+}
+
+// Keeps one station per site, the one of the preferred provider among the
+// stations with a fresh measurement. IDs of the remaining ones are listed
+// as its alternates. Stale measurements are expected to be marked already.
+pub fn deduplicate(
+    stations: Vec<Station>,
+    measurements: Vec<Measurement>,
+    config: &DedupConfig,
+) -> (Vec<Station>, Vec<Measurement>) {
+    let fresh_ids: HashSet<&String> = measurements
+        .iter()
+        .filter(|measurement| !measurement.stale)
+        .map(|measurement| &measurement.station_id)
+        .collect();
+
+    let mut stations = stations;
+    stations.sort_by(|a, b| {
+        fresh_ids
+            .contains(&b.id)
+            .cmp(&fresh_ids.contains(&a.id))
+            .then_with(|| {
+                config
+                    .priority(&a.provider)
+                    .cmp(&config.priority(&b.provider))
+            })
+            .then_with(|| a.id.cmp(&b.id))
+    });
+
+    let mut kept: Vec<Station> = vec![];
+    let mut merged_count = 0;
+    for station in stations {
+        match kept.iter().position(|k| config.is_same_site(k, &station)) {
+            Some(index) => {
+                kept[index].alternates.push(station.id);
+                merged_count += 1;
+            }
+            None => kept.push(station),
+        }
+    }
+
+    if merged_count > 0 {
+        println!("Merged {} duplicated stations", merged_count);
+    }
+
+    let kept_ids: HashSet<String> = kept.iter().map(|station| station.id.clone()).collect();
+    let measurements = measurements
+        .into_iter()
+        .filter(|measurement| kept_ids.contains(&measurement.station_id))
+        .collect();
+
+    (kept, measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn station(id: &str, provider: &str) -> Station {
+        Station {
+            id: id.to_owned(),
+            name: id.to_owned(),
+            elevation: Some(800),
+            url: String::new(),
+            lat: 41.6,
+            long: 1.8,
+            available: true,
+            provider: provider.to_owned(),
+            vendor_id: id.to_owned(),
+            alternates: vec![],
+        }
+    }
+
+    fn measurement(station_id: &str, stale: bool) -> Measurement {
+        Measurement {
+            station_id: station_id.to_owned(),
+            stale,
+            ..Default::default()
+        }
+    }
+
+    fn config() -> DedupConfig {
+        DedupConfig {
+            max_distance_m: 150.0,
+            max_elevation_diff_m: 50,
+            provider_priority: vec!["meteocat".to_owned(), "meteoclimatic".to_owned()],
+        }
+    }
+
+    #[test]
+    fn deduplicate_keeps_preferred_provider() {
+        let stations = vec![station("b", "meteoclimatic"), station("a", "meteocat")];
+        let measurements = vec![measurement("a", false), measurement("b", false)];
+        let (stations, measurements) = deduplicate(stations, measurements, &config());
+        assert_eq!(stations.len(), 1);
+        assert_eq!(stations[0].id, "a");
+        assert_eq!(stations[0].alternates, ["b"]);
+        assert_eq!(measurements.len(), 1);
+    }
+
+    #[test]
+    fn deduplicate_prefers_fresh_measurement() {
+        let stations = vec![station("a", "meteocat"), station("b", "meteoclimatic")];
+        let measurements = vec![measurement("a", true), measurement("b", false)];
+        let (stations, measurements) = deduplicate(stations, measurements, &config());
+        assert_eq!(stations[0].id, "b");
+        assert_eq!(stations[0].alternates, ["a"]);
+        assert_eq!(measurements[0].station_id, "b");
+
+        // Stale measurements dropped by the filter count the same.
+        let stations = vec![station("a", "meteocat"), station("b", "meteoclimatic")];
+        let (stations, _) = deduplicate(stations, vec![measurement("b", false)], &config());
+        assert_eq!(stations[0].id, "b");
+    }
+
+    #[test]
+    fn deduplicate_ignores_unknown_elevation() {
+        let mut other = station("b", "pioupiou");
+        other.elevation = None;
+        let stations = vec![station("a", "meteocat"), other];
+        let measurements = vec![measurement("a", false), measurement("b", false)];
+        let (stations, _) = deduplicate(stations, measurements, &config());
+        assert_eq!(stations.len(), 1);
+
+        let mut other = station("b", "pioupiou");
+        other.elevation = Some(900);
+        let stations = vec![station("a", "meteocat"), other];
+        let measurements = vec![measurement("a", false), measurement("b", false)];
+        let (stations, _) = deduplicate(stations, measurements, &config());
+        assert_eq!(stations.len(), 2);
+    }
+}
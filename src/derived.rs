@@ -0,0 +1,91 @@
+// Quantities derived from the measurements, used by pilots as proxies
+// of turbulence and of the cold felt in flight.
+
+use crate::common::{parse_last_update, DerivedValues, Measurement};
+use crate::history::Sample;
+use crate::wind::circular_std_dev;
+
+use chrono::Duration;
+
+// Gust factor of light winds says nothing about turbulence.
+const GUST_FACTOR_MIN_WIND_SPEED: f64 = 5.0;
+// Wind chill is defined for temperatures up to 10 °C and winds from 4.8 km/h.
+const WIND_CHILL_MAX_TEMPERATURE: f64 = 10.0;
+const WIND_CHILL_MIN_WIND_SPEED: f64 = 4.8;
+// Spread of the directions measured in the last hour, if there are enough of them.
+const SPREAD_WINDOW_MINUTES: i64 = 60;
+const SPREAD_MIN_SAMPLES: usize = 3;
+
+// Gusts divided by the mean wind speed.
+pub fn gust_factor(wind_speed: f64, gusts_speed: f64) -> Option<f64> {
+    (wind_speed >= GUST_FACTOR_MIN_WIND_SPEED)
+        .then(|| (gusts_speed / wind_speed * 100.0).round() / 100.0)
+}
+
+// Wind chill index of Environment Canada and the US National Weather Service, in °C:
+// 13.12 + 0.6215 T - 11.37 V^0.16 + 0.3965 T V^0.16, T in °C and V in km/h.
+pub fn wind_chill(temperature: f64, wind_speed: f64) -> Option<f64> {
+    if temperature > WIND_CHILL_MAX_TEMPERATURE || wind_speed < WIND_CHILL_MIN_WIND_SPEED {
+        return None;
+    }
+    let v = wind_speed.powf(0.16);
+    let wind_chill = 13.12 + 0.6215 * temperature - 11.37 * v + 0.3965 * temperature * v;
+    Some((wind_chill * 10.0).round() / 10.0)
+}
+
+pub fn derive(measurement: &Measurement, samples: &[Sample]) -> DerivedValues {
+    let wind_speed = measurement.wind_speed as f64;
+
+    let wind_direction_spread = parse_last_update(&measurement.last_update).and_then(|time| {
+        let since = time - Duration::minutes(SPREAD_WINDOW_MINUTES);
+        let directions: Vec<f64> = samples
+            .iter()
+            .filter(|sample| sample.time().is_some_and(|t| t > since && t <= time))
+            .filter_map(|sample| sample.wind_direction)
+            .collect();
+        if directions.len() < SPREAD_MIN_SAMPLES {
+            return None;
+        }
+        circular_std_dev(directions).map(|spread| spread.round())
+    });
+
+    DerivedValues {
+        gust_factor: measurement
+            .gusts_speed
+            .and_then(|gusts_speed| gust_factor(wind_speed, gusts_speed as f64)),
+        wind_chill: measurement
+            .temperature
+            .and_then(|temperature| wind_chill(temperature, wind_speed)),
+        wind_direction_spread,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn gust_factor_of_wind() {
+        assert_eq!(gust_factor(20.0, 30.0), Some(1.5));
+        assert_eq!(gust_factor(15.0, 22.0), Some(1.47));
+        assert_eq!(gust_factor(5.0, 10.0), Some(2.0));
+    }
+
+    #[test]
+    fn gust_factor_skips_light_wind() {
+        assert_eq!(gust_factor(4.9, 15.0), None);
+        assert_eq!(gust_factor(0.0, 5.0), None);
+    }
+
+    #[test]
+    fn wind_chill_of_cold_wind() {
+        assert_eq!(wind_chill(-10.0, 20.0), Some(-17.9));
+        assert_eq!(wind_chill(10.0, 4.8), Some(9.8));
+    }
+
+    #[test]
+    fn wind_chill_out_of_range() {
+        assert_eq!(wind_chill(10.1, 20.0), None);
+        assert_eq!(wind_chill(-10.0, 4.7), None);
+    }
+}
@@ -0,0 +1,149 @@
+// Receiver of the "customized server" upload protocol of Ecowitt consoles.
+// Ambient Weather consoles send the same fields in the query string.
+
+use crate::common::{fahrenheit_to_celsius, inches_to_mm, inhg_to_hpa, mph_to_kmh};
+use crate::ingest::{self, Reading, StationConfig};
+
+use chrono::{NaiveDateTime, Utc};
+use std::collections::HashMap;
+
+// Returns vendor ID of the station and its reading.
+pub fn parse_upload(
+    query: &str,
+    body: &[u8],
+    stations: &[StationConfig],
+) -> anyhow::Result<(String, Reading)> {
+    let body = String::from_utf8_lossy(body);
+    let fields: HashMap<String, String> = querystring::querify(query)
+        .into_iter()
+        .chain(querystring::querify(&body))
+        .map(|(key, value)| (key.to_owned(), url_decode(value)))
+        .collect();
+
+    let passkey = fields
+        .get("PASSKEY")
+        .or_else(|| fields.get("MAC"))
+        .ok_or_else(|| anyhow::anyhow!("Missing PASSKEY"))?;
+    // The passkey is a secret of the console, it's neither logged nor echoed.
+    let station = stations
+        .iter()
+        .find(|station| station.passkey.as_ref() == Some(passkey))
+        .ok_or_else(|| anyhow::anyhow!("Unknown PASSKEY"))?;
+
+    let number = |key: &str| fields.get(key).and_then(|v| v.trim().parse::<f64>().ok());
+
+    let now = Utc::now();
+    let last_update = match fields.get("dateutc").map(|v| v.as_str()) {
+        None | Some("now") => now,
+        Some(dateutc) => NaiveDateTime::parse_from_str(dateutc, "%Y-%m-%d %H:%M:%S")
+            .map_err(|e| anyhow::anyhow!("Invalid dateutc '{}': {}", dateutc, e))?
+            .and_utc(),
+    };
+
+    let reading = Reading {
+        wind_speed: number("windspeedmph")
+            .map(mph_to_kmh)
+            .ok_or_else(|| anyhow::anyhow!("Missing windspeedmph"))?,
+        wind_direction: number("winddir"),
+        gusts_speed: number("windgustmph").map(mph_to_kmh),
+        temperature: number("tempf").map(fahrenheit_to_celsius),
+        humidity: number("humidity"),
+        pressure: number("baromrelin").map(inhg_to_hpa),
+        precipitation: number("dailyrainin").map(inches_to_mm),
+        last_update: last_update.format("%Y-%m-%dT%H:%MZ").to_string(),
+    };
+    ingest::validate(&reading, last_update, now)?;
+
+    Ok((station.vendor_id.to_owned(), reading))
+}
+
+fn url_decode(value: &str) -> String {
+    let bytes = value.as_bytes();
+    let mut decoded = Vec::with_capacity(bytes.len());
+    let mut i = 0;
+    while i < bytes.len() {
+        match bytes[i] {
+            b'+' => decoded.push(b' '),
+            b'%' => {
+                let byte = value
+                    .get(i + 1..i + 3)
+                    .and_then(|hex| u8::from_str_radix(hex, 16).ok());
+                if let Some(byte) = byte {
+                    decoded.push(byte);
+                    i += 2;
+                } else {
+                    decoded.push(b'%');
+                }
+            }
+            byte => decoded.push(byte),
+        }
+        i += 1;
+    }
+    String::from_utf8_lossy(&decoded).into_owned()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations() -> Vec<StationConfig> {
+        serde_json::from_str(
+            r#"[{"vendor_id": "club-01", "name": "Club take-off", "lat": 41.6, "long": 1.8,
+                 "elevation": 820, "passkey": "ABCDEF0123456789"}]"#,
+        )
+        .unwrap()
+    }
+
+    #[test]
+    fn parse_upload_of_ecowitt_console() {
+        let body = "PASSKEY=ABCDEF0123456789&stationtype=EasyWeatherV1.6.4\
+            &dateutc=2024-05-12+10%3A05%3A00&tempf=59.0&humidity=48&winddir=315\
+            &windspeedmph=10.0&windgustmph=18.5&baromrelin=30.01&dailyrainin=0.12\
+            &model=WS2900_V2.01.18";
+        let (vendor_id, reading) = parse_upload("", body.as_bytes(), &stations()).unwrap();
+        assert_eq!(vendor_id, "club-01");
+        assert_eq!(reading.last_update, "2024-05-12T10:05Z");
+        assert!((reading.wind_speed - 16.09).abs() < 0.01);
+        assert!((reading.gusts_speed.unwrap() - 29.77).abs() < 0.01);
+        assert_eq!(reading.wind_direction, Some(315.0));
+        assert!((reading.temperature.unwrap() - 15.0).abs() < 0.01);
+        assert_eq!(reading.humidity, Some(48.0));
+        assert!((reading.pressure.unwrap() - 1016.25).abs() < 0.01);
+        assert!((reading.precipitation.unwrap() - 3.05).abs() < 0.01);
+    }
+
+    #[test]
+    fn parse_upload_of_ambient_weather_console() {
+        let query = "PASSKEY=ABCDEF0123456789&dateutc=2024-05-12%2010:05:00\
+            &winddir=90&windspeedmph=5.5&tempf=41.0";
+        let (vendor_id, reading) = parse_upload(query, b"", &stations()).unwrap();
+        assert_eq!(vendor_id, "club-01");
+        assert_eq!(reading.last_update, "2024-05-12T10:05Z");
+        assert!((reading.wind_speed - 8.85).abs() < 0.01);
+        assert_eq!(reading.gusts_speed, None);
+        assert!((reading.temperature.unwrap() - 5.0).abs() < 0.01);
+    }
+
+    #[test]
+    fn parse_upload_hides_unknown_passkey() {
+        let query = "PASSKEY=SECRET42&dateutc=now&windspeedmph=5.5";
+        let e = parse_upload(query, b"", &stations()).unwrap_err();
+        assert_eq!(e.to_string(), "Unknown PASSKEY");
+    }
+
+    #[test]
+    fn parse_upload_requires_wind_speed() {
+        let query = "PASSKEY=ABCDEF0123456789&dateutc=now&tempf=41.0";
+        let e = parse_upload(query, b"", &stations()).unwrap_err();
+        assert_eq!(e.to_string(), "Missing windspeedmph");
+    }
+
+    #[test]
+    fn url_decode_of_percent_and_plus() {
+        assert_eq!(url_decode("2024-05-12+10%3A05%3A00"), "2024-05-12 10:05:00");
+        assert_eq!(url_decode("EasyWeather%2FV1.6"), "EasyWeather/V1.6");
+        assert_eq!(url_decode("100%"), "100%");
+        assert_eq!(url_decode("%zz"), "%zz");
+        assert_eq!(url_decode("caf%C3%A9"), "café");
+    }
+}
@@ -0,0 +1,257 @@
+use crate::common::{parse_last_update, Measurement, Station};
+
+use chrono::{DateTime, Duration, Utc};
+use serde::{Deserialize, Serialize};
+use spin_sdk::key_value::Store;
+use std::collections::{HashMap, HashSet};
+
+pub const RETENTION_HOURS: i64 = 24;
+// The latest sample of a station is kept longer, so that a station that
+// went silent can be told apart from one that reports again.
+const LATEST_RETENTION_DAYS: i64 = 7;
+
+const PROVIDERS_KEY: &str = "history:providers";
+
+// Measurement of a station recorded in the past. Values flagged
+// by the quality control aren't recorded.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Sample {
+    pub last_update: String,
+    pub wind_speed: Option<u64>,
+    pub wind_direction: Option<f64>,
+    pub gusts_speed: Option<u64>,
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub pressure: Option<f64>,
+}
+
+impl Sample {
+    fn from_measurement(measurement: &Measurement) -> Sample {
+        let clean = |field: &str| !measurement.qc_flags.contains_key(field);
+        Sample {
+            last_update: measurement.last_update.to_owned(),
+            wind_speed: Some(measurement.wind_speed).filter(|_| clean("wind_speed")),
+            wind_direction: measurement
+                .wind_direction
+                .filter(|_| clean("wind_direction")),
+            gusts_speed: measurement.gusts_speed.filter(|_| clean("gusts_speed")),
+            temperature: measurement.temperature.filter(|_| clean("temperature")),
+            humidity: measurement.humidity.filter(|_| clean("humidity")),
+            pressure: measurement.pressure.filter(|_| clean("pressure")),
+        }
+    }
+
+    pub fn time(&self) -> Option<DateTime<Utc>> {
+        parse_last_update(&self.last_update)
+    }
+}
+
+// Samples of the stations of a provider, oldest first.
+type ProviderSamples = HashMap<String, Vec<Sample>>;
+
+// Recent measurements of every station, kept in the default key-value store.
+// Samples of each provider are kept under a single key, so that recording
+// them takes one write per provider.
+#[derive(Default)]
+pub struct History {
+    providers: HashMap<String, ProviderSamples>,
+    changed_providers: HashSet<String>,
+}
+
+fn provider_key(provider: &str) -> String {
+    format!("history:{}", provider)
+}
+
+pub fn load() -> anyhow::Result<History> {
+    let store = Store::open_default()?;
+    let provider_names = store
+        .get_json::<Vec<String>>(PROVIDERS_KEY)?
+        .unwrap_or_default();
+    let mut providers = HashMap::new();
+    for provider in provider_names {
+        let samples = store
+            .get_json::<ProviderSamples>(provider_key(&provider))?
+            .unwrap_or_default();
+        providers.insert(provider, samples);
+    }
+    Ok(History {
+        providers,
+        changed_providers: HashSet::new(),
+    })
+}
+
+impl History {
+    // Oldest first.
+    pub fn samples(&self, station_id: &str) -> &[Sample] {
+        self.providers
+            .values()
+            .find_map(|samples| samples.get(station_id))
+            .map(|samples| samples.as_slice())
+            .unwrap_or_default()
+    }
+
+    // Appends the measurements newer than the last recorded ones and forgets
+    // the samples older than the retention. Returns number of the new samples.
+    pub fn record(
+        &mut self,
+        measurements: &[Measurement],
+        stations: &[Station],
+        now: DateTime<Utc>,
+    ) -> usize {
+        let providers: HashMap<&String, &String> = stations
+            .iter()
+            .map(|station| (&station.id, &station.provider))
+            .collect();
+
+        let mut recorded_count = 0;
+        for measurement in measurements {
+            let Some(provider) = providers.get(&measurement.station_id) else {
+                continue;
+            };
+            let Some(time) = parse_last_update(&measurement.last_update) else {
+                continue;
+            };
+            let samples = self
+                .providers
+                .entry(provider.to_string())
+                .or_default()
+                .entry(measurement.station_id.to_owned())
+                .or_default();
+            let last_time = samples.last().and_then(|sample| sample.time());
+            if last_time.is_some_and(|last_time| last_time >= time) {
+                continue;
+            }
+            samples.push(Sample::from_measurement(measurement));
+            self.changed_providers.insert(provider.to_string());
+            recorded_count += 1;
+        }
+
+        let oldest_allowed = now - Duration::hours(RETENTION_HOURS);
+        let oldest_latest_allowed = now - Duration::days(LATEST_RETENTION_DAYS);
+        for (provider, provider_samples) in self.providers.iter_mut() {
+            let count = provider_samples
+                .values()
+                .map(|samples| samples.len())
+                .sum::<usize>();
+            for samples in provider_samples.values_mut() {
+                let latest_index = samples.len().saturating_sub(1);
+                let mut index = 0;
+                samples.retain(|sample| {
+                    let oldest_allowed = if index == latest_index {
+                        oldest_latest_allowed
+                    } else {
+                        oldest_allowed
+                    };
+                    index += 1;
+                    sample.time().is_some_and(|time| time >= oldest_allowed)
+                });
+            }
+            provider_samples.retain(|_, samples| !samples.is_empty());
+            if provider_samples
+                .values()
+                .map(|samples| samples.len())
+                .sum::<usize>()
+                != count
+            {
+                self.changed_providers.insert(provider.to_owned());
+            }
+        }
+
+        recorded_count
+    }
+
+    // Writes samples of the providers changed since loaded.
+    pub fn save(&mut self) -> anyhow::Result<()> {
+        if self.changed_providers.is_empty() {
+            return Ok(());
+        }
+        let store = Store::open_default()?;
+        for provider in self.changed_providers.drain() {
+            if let Some(samples) = self.providers.get(&provider) {
+                store.set_json(provider_key(&provider), samples)?;
+            }
+        }
+        let mut provider_names = self.providers.keys().collect::<Vec<&String>>();
+        provider_names.sort();
+        store.set_json(PROVIDERS_KEY, &provider_names)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-12T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn station(id: &str) -> Station {
+        Station {
+            id: id.to_owned(),
+            name: id.to_uppercase(),
+            elevation: None,
+            url: String::new(),
+            lat: 41.5,
+            long: 2.0,
+            available: true,
+            provider: "meteoclimatic".to_owned(),
+            vendor_id: id.to_owned(),
+            alternates: vec![],
+        }
+    }
+
+    fn measurement(station_id: &str, hours_ago: i64) -> Measurement {
+        Measurement {
+            station_id: station_id.to_owned(),
+            last_update: (now() - Duration::hours(hours_ago))
+                .format("%Y-%m-%dT%H:%MZ")
+                .to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn times(history: &History, station_id: &str) -> Vec<String> {
+        history
+            .samples(station_id)
+            .iter()
+            .map(|sample| sample.last_update.to_owned())
+            .collect()
+    }
+
+    #[test]
+    fn record_appends_newer_measurements_only() {
+        let stations = [station("a")];
+        let mut history = History::default();
+        assert_eq!(history.record(&[measurement("a", 2)], &stations, now()), 1);
+        assert_eq!(history.record(&[measurement("a", 1)], &stations, now()), 1);
+        assert_eq!(history.record(&[measurement("a", 1)], &stations, now()), 0);
+        assert_eq!(history.record(&[measurement("a", 3)], &stations, now()), 0);
+        assert_eq!(
+            times(&history, "a"),
+            ["2024-05-12T10:00Z", "2024-05-12T11:00Z"]
+        );
+        // Stations of unknown providers aren't recorded.
+        assert_eq!(history.record(&[measurement("b", 0)], &stations, now()), 0);
+    }
+
+    #[test]
+    fn record_keeps_latest_sample_of_silent_stations() {
+        let stations = [station("a"), station("b")];
+        let mut history = History::default();
+        history.record(
+            &[measurement("a", 30), measurement("b", 200)],
+            &stations,
+            now() - Duration::hours(1),
+        );
+        history.record(
+            &[measurement("a", 26)],
+            &stations,
+            now() - Duration::hours(1),
+        );
+        history.record(&[], &stations, now());
+        assert_eq!(times(&history, "a"), ["2024-05-11T10:00Z"]);
+        assert!(history.samples("b").is_empty());
+    }
+}
@@ -0,0 +1,227 @@
+use crate::common::{fetch_utf8, station_id_of, Measurement, Station};
+
+use chrono::NaiveDateTime;
+use serde::Deserialize;
+use std::collections::HashMap;
+
+const LIVE_URL: &str = "https://api.holfuy.com/live/";
+const STATIONS_URL: &str = "https://api.holfuy.com/stations/stations.json";
+const STATION_BASE_URL: &str = "https://holfuy.com/en/weather";
+
+#[derive(Debug, Deserialize)]
+struct WindRaw {
+    speed: Option<f64>,
+    gust: Option<f64>,
+    direction: Option<f64>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct MeasurementRaw {
+    stationId: u64,
+    dateTime: String,
+    wind: Option<WindRaw>,
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+    pressure: Option<f64>,
+}
+
+// Single station is returned as an object, multiple as a list.
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum LiveRaw {
+    Many { measurements: Vec<MeasurementRaw> },
+    One(MeasurementRaw),
+}
+
+#[derive(Debug, Deserialize)]
+struct LocationRaw {
+    latitude: f64,
+    longitude: f64,
+    altitude: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationRaw {
+    id: u64,
+    name: String,
+    location: LocationRaw,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct StationsRaw {
+    holfuyStationsList: Vec<StationRaw>,
+}
+
+pub struct Config {
+    api_key: String,
+    station_ids: Vec<String>,
+}
+
+pub fn config() -> anyhow::Result<Option<Config>> {
+    let api_key = spin_sdk::variables::get("holfuy_api_key")?;
+    let station_ids = spin_sdk::variables::get("holfuy_stations")?
+        .split(',')
+        .map(|id| id.trim().to_owned())
+        .filter(|id| !id.is_empty())
+        .collect::<Vec<String>>();
+    if api_key.is_empty() || station_ids.is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(Config {
+        api_key,
+        station_ids,
+    }))
+}
+
+pub async fn fetch_data(config: &Config) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+    println!("[holfuy] Fetching...");
+    let stations_body = fetch_utf8(STATIONS_URL).await?;
+
+    // Wind speed in km/h, temperature in °C and time in UTC.
+    let live_url = format!(
+        "{}?s={}&pw={}&m=JSON&tu=C&su=km/h&utc",
+        LIVE_URL,
+        config.station_ids.join(","),
+        config.api_key
+    );
+    let live_body = fetch_utf8(&live_url).await?;
+
+    println!("Analyzing...");
+    let stations_raw: StationsRaw = serde_json::from_str(&stations_body)?;
+    let live_raw: LiveRaw = serde_json::from_str(&live_body)?;
+    Ok(convert(stations_raw, live_raw))
+}
+
+fn convert(stations_raw: StationsRaw, live_raw: LiveRaw) -> (Vec<Station>, Vec<Measurement>) {
+    let stations_raw: HashMap<u64, StationRaw> = stations_raw
+        .holfuyStationsList
+        .into_iter()
+        .map(|station| (station.id, station))
+        .collect();
+
+    let measurements_raw = match live_raw {
+        LiveRaw::Many { measurements } => measurements,
+        LiveRaw::One(measurement) => vec![measurement],
+    };
+
+    let mut available_stations: Vec<Station> = vec![];
+    let mut measurements: Vec<Measurement> = vec![];
+
+    for measurement_raw in measurements_raw {
+        let vendor_id = measurement_raw.stationId;
+        let Some(station_raw) = stations_raw.get(&vendor_id) else {
+            println!("[{}] Station details unavailable", vendor_id);
+            continue;
+        };
+        let Some(wind) = measurement_raw.wind else {
+            println!("[{}] Wind information not available", vendor_id);
+            continue;
+        };
+        let Some(wind_speed) = wind.speed.map(|v| v.round() as u64) else {
+            println!("[{}] Wind information not available", vendor_id);
+            continue;
+        };
+        let last_update =
+            match NaiveDateTime::parse_from_str(&measurement_raw.dateTime, "%Y-%m-%d %H:%M:%S") {
+                Ok(date_time) => date_time.format("%Y-%m-%dT%H:%MZ").to_string(),
+                Err(e) => {
+                    println!(
+                        "[{}] Invalid time '{}': {}",
+                        vendor_id, measurement_raw.dateTime, e
+                    );
+                    continue;
+                }
+            };
+
+        let station_url = format!("{}/{}", STATION_BASE_URL, vendor_id);
+        let station_id = station_id_of("holfuy", &vendor_id.to_string());
+
+        let measurement = Measurement {
+            station_id: station_id.clone(),
+            wind_speed,
+            wind_direction: wind.direction.filter(|_| wind_speed != 0),
+            gusts_speed: wind.gust.map(|v| v.round() as u64),
+            temperature: measurement_raw.temperature,
+            humidity: measurement_raw.humidity,
+            pressure: measurement_raw.pressure,
+            last_update,
+            ..Default::default()
+        };
+        let station = Station {
+            id: station_id,
+            name: station_raw.name.to_owned(),
+            elevation: station_raw
+                .location
+                .altitude
+                .map(|altitude| altitude.round() as i64),
+            url: station_url,
+            lat: station_raw.location.latitude,
+            long: station_raw.location.longitude,
+            available: true,
+            provider: "holfuy".to_owned(),
+            vendor_id: vendor_id.to_string(),
+            alternates: vec![],
+        };
+        available_stations.push(station);
+        measurements.push(measurement);
+    }
+
+    (available_stations, measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations_raw() -> StationsRaw {
+        serde_json::from_str(include_str!("../tests/fixtures/holfuy_stations.json")).unwrap()
+    }
+
+    #[test]
+    fn convert_of_many_stations() {
+        let live_raw: LiveRaw =
+            serde_json::from_str(include_str!("../tests/fixtures/holfuy_live.json")).unwrap();
+        let (stations, measurements) = convert(stations_raw(), live_raw);
+
+        // Stations without details or without wind are skipped.
+        assert_eq!(stations.len(), 2);
+        assert_eq!(measurements.len(), 2);
+
+        let station = &stations[0];
+        assert_eq!(station.id, station_id_of("holfuy", "1213"));
+        assert_eq!(station.name, "Organyà");
+        assert_eq!(station.url, "https://holfuy.com/en/weather/1213");
+        assert_eq!(station.elevation, Some(1580));
+        assert_eq!((station.lat, station.long), (42.2178, 1.3291));
+
+        let measurement = &measurements[0];
+        assert_eq!(measurement.station_id, station.id);
+        assert_eq!(measurement.last_update, "2024-05-12T10:14Z");
+        assert_eq!(measurement.wind_speed, 17);
+        assert_eq!(measurement.gusts_speed, Some(28));
+        assert_eq!(measurement.wind_direction, Some(217.0));
+        assert_eq!(measurement.temperature, Some(15.3));
+        assert_eq!(measurement.humidity, Some(45.5));
+        assert_eq!(measurement.pressure, Some(1015.0));
+
+        // Unknown altitude and no direction of calm wind.
+        assert_eq!(stations[1].elevation, None);
+        assert_eq!(measurements[1].wind_speed, 0);
+        assert_eq!(measurements[1].wind_direction, None);
+        assert_eq!(measurements[1].humidity, None);
+    }
+
+    #[test]
+    fn convert_of_one_station() {
+        let live_raw: LiveRaw =
+            serde_json::from_str(include_str!("../tests/fixtures/holfuy_live_one.json")).unwrap();
+        let (stations, measurements) = convert(stations_raw(), live_raw);
+        assert_eq!(stations.len(), 1);
+        assert_eq!(stations[0].vendor_id, "1213");
+        assert_eq!(measurements[0].last_update, "2024-05-12T10:24Z");
+        assert_eq!(measurements[0].wind_speed, 20);
+        assert_eq!(measurements[0].gusts_speed, Some(32));
+    }
+}
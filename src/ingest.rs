@@ -0,0 +1,326 @@
+use crate::common::{station_id_of, Measurement, Station};
+
+use chrono::{DateTime, Utc};
+use serde::{Deserialize, Serialize};
+use spin_sdk::key_value::Store;
+use std::collections::HashMap;
+
+// Every station has its own entry, so that concurrent uploads of different
+// stations don't overwrite each other.
+fn store_key(vendor_id: &str) -> String {
+    format!("ingest:{}", vendor_id)
+}
+
+// Club-owned station that pushes its readings.
+#[derive(Debug, Deserialize)]
+pub struct StationConfig {
+    pub vendor_id: String,
+    name: String,
+    lat: f64,
+    long: f64,
+    elevation: i64,
+    url: Option<String>,
+    // Key of Ecowitt and Ambient Weather consoles.
+    pub passkey: Option<String>,
+    // Token of the station itself, it lets it push only its own readings.
+    token: Option<String>,
+}
+
+impl StationConfig {
+    fn url(&self) -> String {
+        self.url
+            .to_owned()
+            .unwrap_or_else(|| format!("urn:zephyr-data-provider:ingest:{}", self.vendor_id))
+    }
+}
+
+// Speeds in km/h, temperature in °C, pressure in hPa, precipitation in mm.
+#[derive(Debug, Clone, Serialize, Deserialize)]
+pub struct Reading {
+    pub wind_speed: f64,
+    pub wind_direction: Option<f64>,
+    pub gusts_speed: Option<f64>,
+    pub temperature: Option<f64>,
+    pub humidity: Option<f64>,
+    pub pressure: Option<f64>,
+    pub precipitation: Option<f64>,
+    pub last_update: String,
+}
+
+#[derive(Debug, Deserialize)]
+struct ReadingRaw {
+    station: String,
+    wind_speed: f64,
+    wind_direction: Option<f64>,
+    gusts_speed: Option<f64>,
+    temperature: Option<f64>,
+    humidity: Option<f64>,
+    pressure: Option<f64>,
+    precipitation: Option<f64>,
+    // Time of the request by default.
+    last_update: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+#[serde(untagged)]
+enum PayloadRaw {
+    One(ReadingRaw),
+    Many(Vec<ReadingRaw>),
+}
+
+pub fn stations() -> anyhow::Result<Vec<StationConfig>> {
+    let config = spin_sdk::variables::get("ingest_stations")?;
+    if config.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    Ok(serde_json::from_str(&config)?)
+}
+
+// Station authenticated by its own token, if any.
+pub fn station_of_token<'a>(
+    stations: &'a [StationConfig],
+    token: &str,
+) -> Option<&'a StationConfig> {
+    stations
+        .iter()
+        .find(|station| station.token.as_deref() == Some(token))
+}
+
+// Validates the payload. Readings are returned with vendor IDs of the stations.
+// Readings of the other stations are rejected when the token is of one station.
+pub fn parse_readings(
+    body: &[u8],
+    stations: &[StationConfig],
+    token_station: Option<&StationConfig>,
+) -> anyhow::Result<Vec<(String, Reading)>> {
+    let payload: PayloadRaw = serde_json::from_slice(body)?;
+    let readings_raw = match payload {
+        PayloadRaw::One(reading_raw) => vec![reading_raw],
+        PayloadRaw::Many(readings_raw) => readings_raw,
+    };
+
+    let now = Utc::now();
+    readings_raw
+        .into_iter()
+        .map(|reading_raw| {
+            let vendor_id = reading_raw.station;
+            if !stations
+                .iter()
+                .any(|station| station.vendor_id == vendor_id)
+            {
+                anyhow::bail!("[{}] Station not registered", vendor_id);
+            }
+            if let Some(token_station) = token_station {
+                if token_station.vendor_id != vendor_id {
+                    anyhow::bail!("[{}] Token of another station", vendor_id);
+                }
+            }
+            let last_update = match reading_raw.last_update {
+                Some(last_update) => DateTime::parse_from_rfc3339(&last_update)
+                    .map_err(|e| anyhow::anyhow!("[{}] Invalid last_update: {}", vendor_id, e))?
+                    .with_timezone(&Utc),
+                None => now,
+            };
+            let reading = Reading {
+                wind_speed: reading_raw.wind_speed,
+                wind_direction: reading_raw.wind_direction,
+                gusts_speed: reading_raw.gusts_speed,
+                temperature: reading_raw.temperature,
+                humidity: reading_raw.humidity,
+                pressure: reading_raw.pressure,
+                precipitation: reading_raw.precipitation,
+                last_update: last_update.format("%Y-%m-%dT%H:%MZ").to_string(),
+            };
+            validate(&reading, last_update, now)
+                .map_err(|e| anyhow::anyhow!("[{}] {}", vendor_id, e))?;
+            Ok((vendor_id, reading))
+        })
+        .collect()
+}
+
+pub fn validate(
+    reading: &Reading,
+    last_update: DateTime<Utc>,
+    now: DateTime<Utc>,
+) -> anyhow::Result<()> {
+    if reading.wind_speed < 0.0 {
+        anyhow::bail!("Invalid wind_speed: {}", reading.wind_speed);
+    }
+    if let Some(gusts_speed) = reading.gusts_speed {
+        if gusts_speed < 0.0 {
+            anyhow::bail!("Invalid gusts_speed: {}", gusts_speed);
+        }
+    }
+    if let Some(wind_direction) = reading.wind_direction {
+        if !(0.0..=360.0).contains(&wind_direction) {
+            anyhow::bail!("Invalid wind_direction: {}", wind_direction);
+        }
+    }
+    if let Some(humidity) = reading.humidity {
+        if !(0.0..=100.0).contains(&humidity) {
+            anyhow::bail!("Invalid humidity: {}", humidity);
+        }
+    }
+    if last_update > now + chrono::Duration::minutes(5) {
+        anyhow::bail!("last_update is in the future");
+    }
+    Ok(())
+}
+
+// Keeps only the latest reading of every station.
+pub fn store_readings(readings: Vec<(String, Reading)>) -> anyhow::Result<()> {
+    let store = Store::open_default()?;
+    let mut latest: HashMap<String, Reading> = HashMap::new();
+    for (vendor_id, reading) in readings {
+        match latest.get(&vendor_id) {
+            Some(other) if other.last_update > reading.last_update => {}
+            _ => {
+                latest.insert(vendor_id, reading);
+            }
+        }
+    }
+    for (vendor_id, reading) in latest {
+        let key = store_key(&vendor_id);
+        match store.get_json::<Reading>(&key)? {
+            Some(other) if other.last_update > reading.last_update => {}
+            _ => store.set_json(&key, &reading)?,
+        }
+    }
+    Ok(())
+}
+
+pub fn fetch_data(stations: &[StationConfig]) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+    println!("[ingest] Fetching...");
+    let store = Store::open_default()?;
+
+    let mut available_stations: Vec<Station> = vec![];
+    let mut measurements: Vec<Measurement> = vec![];
+
+    for station_config in stations {
+        let Some(reading) = store.get_json::<Reading>(store_key(&station_config.vendor_id))? else {
+            continue;
+        };
+        let station_url = station_config.url();
+        let station_id = station_id_of("ingest", &station_config.vendor_id);
+        let wind_speed = reading.wind_speed.round() as u64;
+
+        let measurement = Measurement {
+            station_id: station_id.clone(),
+            wind_speed,
+            wind_direction: reading.wind_direction.filter(|_| wind_speed != 0),
+            gusts_speed: reading.gusts_speed.map(|v| v.round() as u64),
+            temperature: reading.temperature,
+            humidity: reading.humidity,
+            pressure: reading.pressure,
+            precipitation: reading.precipitation,
+            last_update: reading.last_update.to_owned(),
+            ..Default::default()
+        };
+        let station = Station {
+            id: station_id,
+            name: station_config.name.to_owned(),
+            elevation: Some(station_config.elevation),
+            url: station_url,
+            lat: station_config.lat,
+            long: station_config.long,
+            available: true,
+            provider: "ingest".to_owned(),
+            vendor_id: station_config.vendor_id.to_owned(),
+            alternates: vec![],
+        };
+        available_stations.push(station);
+        measurements.push(measurement);
+    }
+
+    Ok((available_stations, measurements))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn stations() -> Vec<StationConfig> {
+        serde_json::from_str(
+            r#"[{"vendor_id": "club-01", "name": "Club take-off", "lat": 41.6, "long": 1.8,
+                 "elevation": 820, "token": "take-off-token"},
+                {"vendor_id": "club-02", "name": "Club landing", "lat": 41.5, "long": 1.8,
+                 "elevation": 310}]"#,
+        )
+        .unwrap()
+    }
+
+    fn parse(body: &str) -> anyhow::Result<Vec<(String, Reading)>> {
+        parse_readings(body.as_bytes(), &stations(), None)
+    }
+
+    #[test]
+    fn parse_readings_of_valid_payloads() {
+        let readings = parse(
+            r#"{"station": "club-01", "wind_speed": 12.5, "wind_direction": 225,
+                "gusts_speed": 20, "temperature": 18.2, "humidity": 55, "pressure": 1013.2,
+                "precipitation": 0.4, "last_update": "2024-05-12T12:05:00+02:00"}"#,
+        )
+        .unwrap();
+        assert_eq!(readings.len(), 1);
+        let (vendor_id, reading) = &readings[0];
+        assert_eq!(vendor_id, "club-01");
+        assert_eq!(reading.wind_speed, 12.5);
+        assert_eq!(reading.wind_direction, Some(225.0));
+        assert_eq!(reading.gusts_speed, Some(20.0));
+        assert_eq!(reading.humidity, Some(55.0));
+        assert_eq!(reading.last_update, "2024-05-12T10:05Z");
+
+        let readings = parse(
+            r#"[{"station": "club-01", "wind_speed": 10},
+                {"station": "club-02", "wind_speed": 0}]"#,
+        )
+        .unwrap();
+        let vendor_ids: Vec<&str> = readings.iter().map(|(id, _)| id.as_str()).collect();
+        assert_eq!(vendor_ids, ["club-01", "club-02"]);
+        assert_eq!(readings[1].1.wind_direction, None);
+    }
+
+    #[test]
+    fn parse_readings_requires_station_and_wind_speed() {
+        assert!(parse(r#"{"wind_speed": 10}"#).is_err());
+        assert!(parse(r#"{"station": "club-01", "wind_direction": 90}"#).is_err());
+        assert!(parse(r#"{"station": "club-01", "wind_speed": "calm"}"#).is_err());
+        assert!(parse("").is_err());
+    }
+
+    #[test]
+    fn parse_readings_rejects_out_of_range_values() {
+        let invalid = [
+            r#"{"station": "club-01", "wind_speed": -1}"#,
+            r#"{"station": "club-01", "wind_speed": 10, "gusts_speed": -5}"#,
+            r#"{"station": "club-01", "wind_speed": 10, "wind_direction": 361}"#,
+            r#"{"station": "club-01", "wind_speed": 10, "humidity": 101}"#,
+            r#"{"station": "club-01", "wind_speed": 10, "last_update": "2100-01-01T00:00:00Z"}"#,
+            r#"{"station": "club-01", "wind_speed": 10, "last_update": "12:05"}"#,
+        ];
+        for body in invalid {
+            assert!(parse(body).is_err(), "{}", body);
+        }
+    }
+
+    #[test]
+    fn parse_readings_rejects_unknown_station() {
+        let e = parse(r#"{"station": "club-99", "wind_speed": 10}"#).unwrap_err();
+        assert_eq!(e.to_string(), "[club-99] Station not registered");
+    }
+
+    #[test]
+    fn parse_readings_of_station_token() {
+        let stations = stations();
+        assert!(station_of_token(&stations, "landing-token").is_none());
+        let token_station = station_of_token(&stations, "take-off-token");
+        assert_eq!(token_station.unwrap().vendor_id, "club-01");
+
+        let body = r#"{"station": "club-01", "wind_speed": 10}"#;
+        assert!(parse_readings(body.as_bytes(), &stations, token_station).is_ok());
+        let body = r#"[{"station": "club-01", "wind_speed": 10},
+                       {"station": "club-02", "wind_speed": 10}]"#;
+        let e = parse_readings(body.as_bytes(), &stations, token_station).unwrap_err();
+        assert_eq!(e.to_string(), "[club-02] Token of another station");
+    }
+}
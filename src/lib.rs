@@ -1,10 +1,35 @@
+mod aemet;
+mod aggregates;
+mod alerts;
+mod aliases;
+mod aviation;
+mod calibration;
 mod common;
+mod custom;
+mod dedup;
+mod derived;
+mod ecowitt;
+mod history;
+mod holfuy;
+mod ingest;
+mod metar;
 mod meteocat;
 mod meteoclimatic;
+mod meteoclimatic_profile;
+mod overrides;
+mod pioupiou;
+mod qc;
+mod sites;
+mod stale;
+mod weatherlink;
+mod wind;
+mod wunderground;
 
+use common::{Measurement, Station};
 use spin_sdk::http::{IntoResponse, Request, Response};
 use spin_sdk::http_component;
-use std::collections::HashMap;
+use std::cell::OnceCell;
+use std::collections::{HashMap, HashSet};
 
 fn log_req_info(req: &Request) {
     let client_addr: &str = req
@@ -36,12 +61,38 @@ fn json_ok_resp(payload: &str) -> Response {
         .build()
 }
 
-fn check_token(req: &Request) -> anyhow::Result<Option<Response>> {
+fn query_params(req: &Request) -> HashMap<&str, &str> {
     let query_string = req.query();
     let query_vector = querystring::querify(query_string);
-    let query: HashMap<_, _> = query_vector.into_iter().collect();
+    query_vector.into_iter().collect()
+}
+
+fn query_flag(req: &Request, name: &str) -> bool {
+    matches!(query_params(req).get(name), Some(&"true") | Some(&"1"))
+}
+
+fn check_token(req: &Request) -> anyhow::Result<Option<Response>> {
+    check_token_of(req, "api_token")
+}
+
+// Endpoints that change data expect a token other than the one of the read clients.
+fn check_admin_token(req: &Request) -> anyhow::Result<Option<Response>> {
+    check_token_of(req, "admin_token")
+}
+
+// Stations that push their readings share a token of their own.
+fn check_ingest_token(req: &Request) -> anyhow::Result<Option<Response>> {
+    check_token_of(req, "ingest_token")
+}
 
-    let expected_token = spin_sdk::variables::get("api_token")?;
+fn check_token_of(req: &Request, variable: &str) -> anyhow::Result<Option<Response>> {
+    let query = query_params(req);
+
+    let expected_token = spin_sdk::variables::get(variable)?;
+    if expected_token.is_empty() {
+        log::error!("Token {} not configured", variable);
+        return Ok(Some(plain_text_resp(403, "Forbidden")));
+    }
 
     if let Some(token) = query.get("token") {
         if token != &expected_token {
@@ -75,14 +126,231 @@ fn handle_get_units(req: &Request) -> anyhow::Result<Response> {
     Ok(json_ok_resp(json.as_str()))
 }
 
+type ProviderData = (Vec<Station>, Vec<Measurement>);
+
+// Failure of a single provider shouldn't hide data of the others.
+fn merge_provider_data(
+    provider: &str,
+    result: anyhow::Result<ProviderData>,
+    overrides: &overrides::Overrides,
+    data: &mut ProviderData,
+) {
+    match result {
+        Ok((stations, measurements)) => {
+            let (stations, measurements) = overrides.apply(stations, measurements);
+            data.0.extend(stations);
+            data.1.extend(measurements);
+        }
+        Err(e) => log::error!("[{}] {}", provider, e),
+    }
+}
+
+// Data of all the providers, stations reported twice are skipped.
+// Slow side work such as fetching station profiles is only done on refresh.
+async fn fetch_providers_data(refresh: bool) -> ProviderData {
+    let mut data: ProviderData = (vec![], vec![]);
+    let overrides = overrides::load().unwrap_or_else(|e| {
+        log::error!("[overrides] {}", e);
+        overrides::Overrides::default()
+    });
+
+    // Invalid configuration of a provider is reported like its failure.
+    match meteoclimatic::regions() {
+        Ok(regions) => {
+            for region in regions {
+                let provider = format!("meteoclimatic/{}", region);
+                let result = meteoclimatic::fetch_data(&region, refresh).await;
+                merge_provider_data(&provider, result, &overrides, &mut data);
+            }
+        }
+        Err(e) => log::error!("[meteoclimatic] {}", e),
+    }
+
+    let result = meteocat::fetch_data(refresh).await;
+    merge_provider_data("meteocat", result, &overrides, &mut data);
+
+    let result = async {
+        match aemet::api_key()? {
+            Some(api_key) => aemet::fetch_data(&api_key).await,
+            None => Ok((vec![], vec![])),
+        }
+    };
+    merge_provider_data("aemet", result.await, &overrides, &mut data);
+
+    let result = async {
+        match holfuy::config()? {
+            Some(config) => holfuy::fetch_data(&config).await,
+            None => Ok((vec![], vec![])),
+        }
+    };
+    merge_provider_data("holfuy", result.await, &overrides, &mut data);
+
+    let result = async {
+        match pioupiou::bbox()? {
+            Some(bbox) => pioupiou::fetch_data(&bbox).await,
+            None => Ok((vec![], vec![])),
+        }
+    };
+    merge_provider_data("pioupiou", result.await, &overrides, &mut data);
+
+    let result = async {
+        let config = wunderground::config()?;
+        if config.is_empty() {
+            return Ok((vec![], vec![]));
+        }
+        wunderground::fetch_data(&config).await
+    };
+    merge_provider_data("wunderground", result.await, &overrides, &mut data);
+
+    let result = async {
+        let config = weatherlink::config()?;
+        if config.is_empty() {
+            return Ok((vec![], vec![]));
+        }
+        weatherlink::fetch_data(&config).await
+    };
+    merge_provider_data("weatherlink", result.await, &overrides, &mut data);
+
+    let result = async {
+        let stations = aviation::stations()?;
+        if stations.is_empty() {
+            return Ok((vec![], vec![]));
+        }
+        aviation::fetch_data(&stations).await
+    };
+    merge_provider_data("aviation", result.await, &overrides, &mut data);
+
+    let result = ingest::stations().and_then(|stations| {
+        if stations.is_empty() {
+            return Ok((vec![], vec![]));
+        }
+        ingest::fetch_data(&stations)
+    });
+    merge_provider_data("ingest", result, &overrides, &mut data);
+
+    match custom::config() {
+        Ok(sources) => {
+            for source in sources {
+                let provider = format!("custom/{}", source.name);
+                let result = custom::fetch_data(&source).await;
+                merge_provider_data(&provider, result, &overrides, &mut data);
+            }
+        }
+        Err(e) => log::error!("[custom] {}", e),
+    }
+
+    let (mut stations, mut measurements) = data;
+
+    // Neighbouring regions may share some of the stations.
+    let mut seen_stations = HashSet::new();
+    stations.retain(|station| seen_stations.insert(station.id.to_owned()));
+    let mut seen_measurements = HashSet::new();
+    measurements.retain(|measurement| seen_measurements.insert(measurement.station_id.to_owned()));
+
+    (stations, measurements)
+}
+
+fn check_quality(
+    measurements: &mut [Measurement],
+    stations: &[Station],
+    history: &history::History,
+) {
+    match qc::config() {
+        Ok(qc_config) => qc::check(measurements, stations, history, &qc_config),
+        Err(e) => log::error!("[qc] {}", e),
+    }
+}
+
+// Recorded history is only read. Read requests go without it rather than
+// fail when it can't be loaded.
+fn load_history() -> history::History {
+    history::load().unwrap_or_else(|e| {
+        log::error!("[history] {}", e);
+        history::History::default()
+    })
+}
+
+// Data of the providers is fetched live, it's recorded only by the scheduled refresh.
+async fn fetch_all_data(req: &Request, with_measurements: bool) -> anyhow::Result<ProviderData> {
+    let (stations, measurements) = fetch_providers_data(false).await;
+    process_data(req, stations, measurements, with_measurements)
+}
+
+// Stages requested by the query, applied to the data of the providers.
+// Stages that only change the measurements are skipped without them.
+fn process_data(
+    req: &Request,
+    mut stations: Vec<Station>,
+    mut measurements: Vec<Measurement>,
+    with_measurements: bool,
+) -> anyhow::Result<ProviderData> {
+    let history = OnceCell::new();
+    let history = || history.get_or_init(load_history);
+
+    // History is needed to tell silent Meteoclimatic stations apart.
+    if stations
+        .iter()
+        .any(|station| station.provider == "meteoclimatic")
+    {
+        meteoclimatic::date_back_silent_readings(&mut measurements, &stations, history());
+    }
+    if with_measurements {
+        check_quality(&mut measurements, &stations, history());
+    }
+
+    // Stages with invalid configuration are skipped. Stale measurements are
+    // found first, so that dedup keeps the stations with fresh ones.
+    let include_stale = query_flag(req, "include_stale");
+    match stale::config() {
+        Ok(stale_config) => {
+            (stations, measurements) = stale::filter_stale(
+                stations,
+                measurements,
+                &stale_config,
+                chrono::Utc::now(),
+                include_stale,
+            );
+        }
+        Err(e) => log::error!("[stale] {}", e),
+    }
+
+    if !query_flag(req, "include_duplicates") {
+        match dedup::config() {
+            Ok(dedup_config) => {
+                (stations, measurements) =
+                    dedup::deduplicate(stations, measurements, &dedup_config);
+            }
+            Err(e) => log::error!("[dedup] {}", e),
+        }
+    }
+
+    if with_measurements && query_flag(req, "wind_details") {
+        let lang = match query_params(req).get("lang") {
+            Some(lang) => wind::Lang::parse(lang)
+                .ok_or_else(|| anyhow::anyhow!("Unsupported language '{}'", lang))?,
+            None => wind::Lang::Catalan,
+        };
+        for measurement in measurements.iter_mut() {
+            let samples = history().samples(&measurement.station_id);
+            measurement.wind_details = Some(wind::details(measurement, samples, lang));
+        }
+    }
+
+    if with_measurements && query_flag(req, "derived") {
+        for measurement in measurements.iter_mut() {
+            let samples = history().samples(&measurement.station_id);
+            measurement.derived = Some(derived::derive(measurement, samples));
+        }
+    }
+
+    Ok((stations, measurements))
+}
+
 async fn handle_get_stations(req: &Request) -> anyhow::Result<Response> {
     if let Some(resp) = check_token(req)? {
         return Ok(resp);
     };
-    let (stations1, _) = meteoclimatic::fetch_data().await?;
-    let (stations2, _) = meteocat::fetch_data().await?;
-
-    let stations = stations1.into_iter().chain(stations2).collect::<Vec<_>>();
+    let (stations, _) = fetch_all_data(req, false).await?;
     let json = serde_json::to_string(&stations)?;
     Ok(json_ok_resp(json.as_str()))
 }
@@ -91,17 +359,92 @@ async fn handle_get_measurements(req: &Request) -> anyhow::Result<Response> {
     if let Some(resp) = check_token(req)? {
         return Ok(resp);
     };
-    let (_, measurements1) = meteoclimatic::fetch_data().await?;
-    let (_, measurements2) = meteocat::fetch_data().await?;
-
-    let measurements = measurements1
-        .into_iter()
-        .chain(measurements2)
-        .collect::<Vec<_>>();
+    let (_, measurements) = fetch_all_data(req, true).await?;
     let json = serde_json::to_string(&measurements)?;
     Ok(json_ok_resp(json.as_str()))
 }
 
+// Recorded aliases, along with the ones of the current stations.
+async fn load_aliases() -> anyhow::Result<aliases::Aliases> {
+    let mut aliases = aliases::load()?;
+    let (stations, _) = fetch_providers_data(false).await;
+    aliases.add(&stations);
+    Ok(aliases)
+}
+
+// Lets clients migrate the station IDs they keep, e.g. favourites.
+async fn handle_get_aliases(req: &Request) -> anyhow::Result<Response> {
+    if let Some(resp) = check_token(req)? {
+        return Ok(resp);
+    };
+    let aliases = load_aliases().await?;
+    let json = serde_json::to_string(aliases.all())?;
+    Ok(json_ok_resp(json.as_str()))
+}
+
+fn handle_get_aggregates(req: &Request) -> anyhow::Result<Response> {
+    if let Some(resp) = check_token(req)? {
+        return Ok(resp);
+    };
+    let query = query_params(req);
+    let Some(station_id) = query.get("station_id") else {
+        return Ok(plain_text_resp(400, "Missing station_id"));
+    };
+    let window = match aggregates::parse_window(query.get("window").unwrap_or(&"1h")) {
+        Ok(window) => window,
+        Err(e) => return Ok(plain_text_resp(400, &e.to_string())),
+    };
+
+    // Samples are recorded by the refresh, along with the aliases of their stations.
+    let aliases = aliases::load()?;
+    let station_id = aliases.resolve(station_id);
+    let history = history::load()?;
+    let samples = history.samples(station_id);
+    let aggregates = aggregates::aggregate(station_id, samples, window, chrono::Utc::now());
+    let json = serde_json::to_string(&aggregates)?;
+    Ok(json_ok_resp(json.as_str()))
+}
+
+fn handle_get_alerts(req: &Request) -> anyhow::Result<Response> {
+    if let Some(resp) = check_admin_token(req)? {
+        return Ok(resp);
+    };
+    let rules = alerts::load()?;
+    let json = serde_json::to_string(&rules.rules)?;
+    Ok(json_ok_resp(json.as_str()))
+}
+
+// Rules refer to the current IDs of the stations.
+async fn parse_rule_config(req: &Request) -> anyhow::Result<alerts::RuleConfig> {
+    let mut config: alerts::RuleConfig = serde_json::from_slice(req.body())?;
+    config.station_id = load_aliases().await?.resolve(&config.station_id).to_owned();
+    config.validate(&alerts::webhook_hosts()?)?;
+    Ok(config)
+}
+
+async fn handle_get_sites(req: &Request) -> anyhow::Result<Response> {
+    if let Some(resp) = check_token(req)? {
+        return Ok(resp);
+    };
+    let mut sites_config = sites::config()?;
+    let (stations, measurements) = fetch_providers_data(false).await;
+    // Aliases of the stations before they're merged with the ones of the same site.
+    let mut aliases = aliases::load()?;
+    aliases.add(&stations);
+    for site_config in sites_config.iter_mut() {
+        for station_id in site_config.station_ids.iter_mut() {
+            *station_id = aliases.resolve(station_id).to_owned();
+        }
+    }
+    let (stations, measurements) = process_data(req, stations, measurements, true)?;
+    let sites: Vec<sites::Site> = sites_config
+        .iter()
+        .map(|site_config| sites::evaluate(site_config, &stations, &measurements))
+        .collect();
+    let json = serde_json::to_string(&sites)?;
+    Ok(json_ok_resp(json.as_str()))
+}
+
 async fn handle_get(req: &Request) -> anyhow::Result<Response> {
     match req.path() {
         "/api/v1/health" => handle_get_health_check(),
@@ -109,6 +452,154 @@ async fn handle_get(req: &Request) -> anyhow::Result<Response> {
         "/api/v1/units" => handle_get_units(req),
         "/api/v1/stations" => handle_get_stations(req).await,
         "/api/v1/measurements" => handle_get_measurements(req).await,
+        "/api/v1/aliases" => handle_get_aliases(req).await,
+        "/api/v1/aggregates" => handle_get_aggregates(req),
+        "/api/v1/alerts" => handle_get_alerts(req),
+        "/api/v1/sites" => handle_get_sites(req).await,
+        "/api/v1/ecowitt" => handle_ecowitt_upload(req),
+        _ => Ok(plain_text_resp(404, "Not Found")),
+    }
+}
+
+// Stations may authenticate with their own token instead of the ingest one.
+fn handle_post_ingest(req: &Request) -> anyhow::Result<Response> {
+    let stations = ingest::stations()?;
+    let token_station = query_params(req)
+        .get("token")
+        .and_then(|token| ingest::station_of_token(&stations, token));
+    if token_station.is_none() {
+        if let Some(resp) = check_ingest_token(req)? {
+            return Ok(resp);
+        };
+    }
+    let readings = match ingest::parse_readings(req.body(), &stations, token_station) {
+        Ok(readings) => readings,
+        Err(e) => {
+            log::error!("Invalid readings: {}", e);
+            return Ok(plain_text_resp(400, &format!("Invalid readings: {}", e)));
+        }
+    };
+    let count = readings.len();
+    ingest::store_readings(readings)?;
+    Ok(plain_text_resp(200, &format!("Accepted {count} readings")))
+}
+
+// Consoles authenticate with their PASSKEY instead of the API token.
+fn handle_ecowitt_upload(req: &Request) -> anyhow::Result<Response> {
+    let stations = ingest::stations()?;
+    let reading = match ecowitt::parse_upload(req.query(), req.body(), &stations) {
+        Ok(reading) => reading,
+        Err(e) => {
+            log::error!("Invalid upload: {}", e);
+            return Ok(plain_text_resp(400, &format!("Invalid upload: {}", e)));
+        }
+    };
+    ingest::store_readings(vec![reading])?;
+    Ok(plain_text_resp(200, "OK"))
+}
+
+async fn handle_post_alert(req: &Request) -> anyhow::Result<Response> {
+    if let Some(resp) = check_admin_token(req)? {
+        return Ok(resp);
+    };
+    let config = match parse_rule_config(req).await {
+        Ok(config) => config,
+        Err(e) => return Ok(plain_text_resp(400, &format!("Invalid rule: {}", e))),
+    };
+    let mut rules = alerts::load()?;
+    let rule = rules.add(config, chrono::Utc::now())?;
+    let json = serde_json::to_string(rule)?;
+    Ok(json_ok_resp(json.as_str()))
+}
+
+// Scheduled pass that records the measurements, to be requested regularly,
+// e.g. every 10 minutes. Read requests don't change any data.
+async fn handle_post_refresh(req: &Request) -> anyhow::Result<Response> {
+    if let Some(resp) = check_admin_token(req)? {
+        return Ok(resp);
+    };
+    let (stations, mut measurements) = fetch_providers_data(true).await;
+    aliases::load()?.record(&stations)?;
+
+    let now = chrono::Utc::now();
+    let mut history = history::load()?;
+    let dated_back_count =
+        meteoclimatic::date_back_silent_readings(&mut measurements, &stations, &history);
+    if dated_back_count > 0 {
+        log::info!(
+            "[meteoclimatic] {} readings unchanged for days",
+            dated_back_count
+        );
+    }
+    check_quality(&mut measurements, &stations, &history);
+    let recorded_count = history.record(&measurements, &stations, now);
+    history.save()?;
+
+    let webhook_hosts = alerts::webhook_hosts()?;
+    alerts::load()?
+        .evaluate(&history, &webhook_hosts, now)
+        .await?;
+
+    Ok(plain_text_resp(
+        200,
+        &format!("Recorded {recorded_count} measurements"),
+    ))
+}
+
+async fn handle_post(req: &Request) -> anyhow::Result<Response> {
+    match req.path() {
+        "/api/v1/ingest" => handle_post_ingest(req),
+        "/api/v1/ecowitt" => handle_ecowitt_upload(req),
+        "/api/v1/alerts" => handle_post_alert(req).await,
+        "/api/v1/refresh" => handle_post_refresh(req).await,
+        _ => Ok(plain_text_resp(404, "Not Found")),
+    }
+}
+
+async fn handle_put_alert(req: &Request) -> anyhow::Result<Response> {
+    if let Some(resp) = check_admin_token(req)? {
+        return Ok(resp);
+    };
+    let query = query_params(req);
+    let Some(id) = query.get("id") else {
+        return Ok(plain_text_resp(400, "Missing id"));
+    };
+    let config = match parse_rule_config(req).await {
+        Ok(config) => config,
+        Err(e) => return Ok(plain_text_resp(400, &format!("Invalid rule: {}", e))),
+    };
+    let mut rules = alerts::load()?;
+    if !rules.replace(id, config)? {
+        return Ok(plain_text_resp(404, "Not Found"));
+    }
+    Ok(plain_text_resp(200, "OK"))
+}
+
+fn handle_delete_alert(req: &Request) -> anyhow::Result<Response> {
+    if let Some(resp) = check_admin_token(req)? {
+        return Ok(resp);
+    };
+    let query = query_params(req);
+    let Some(id) = query.get("id") else {
+        return Ok(plain_text_resp(400, "Missing id"));
+    };
+    let mut rules = alerts::load()?;
+    if !rules.remove(id)? {
+        return Ok(plain_text_resp(404, "Not Found"));
+    }
+    Ok(plain_text_resp(200, "OK"))
+}
+
+async fn handle_put(req: &Request) -> anyhow::Result<Response> {
+    match req.path() {
+        "/api/v1/alerts" => handle_put_alert(req).await,
+        _ => Ok(plain_text_resp(404, "Not Found")),
+    }
+}
+
+fn handle_delete(req: &Request) -> anyhow::Result<Response> {
+    match req.path() {
+        "/api/v1/alerts" => handle_delete_alert(req),
         _ => Ok(plain_text_resp(404, "Not Found")),
     }
 }
@@ -120,6 +611,9 @@ async fn handle_zephyr_data_provider(req: Request) -> anyhow::Result<impl IntoRe
 
     match req.method() {
         spin_sdk::http::Method::Get => handle_get(&req).await,
+        spin_sdk::http::Method::Post => handle_post(&req).await,
+        spin_sdk::http::Method::Put => handle_put(&req).await,
+        spin_sdk::http::Method::Delete => handle_delete(&req),
         _ => Ok(plain_text_resp(405, "Method not allowed")),
     }
 }
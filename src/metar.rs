@@ -0,0 +1,336 @@
+// Decoder of METAR reports, limited to the groups relevant for this service:
+// station, observation time, wind, temperature and pressure.
+
+use crate::common::{inhg_to_hpa, knots_to_kmh, ms_to_kmh};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum SpeedUnit {
+    Knots,
+    MetersPerSecond,
+    KilometersPerHour,
+}
+
+impl SpeedUnit {
+    fn parse(unit: &str) -> Option<SpeedUnit> {
+        match unit {
+            "KT" => Some(SpeedUnit::Knots),
+            "MPS" => Some(SpeedUnit::MetersPerSecond),
+            "KMH" => Some(SpeedUnit::KilometersPerHour),
+            _ => None,
+        }
+    }
+
+    pub fn to_kmh(self, speed: f64) -> f64 {
+        match self {
+            SpeedUnit::Knots => knots_to_kmh(speed),
+            SpeedUnit::MetersPerSecond => ms_to_kmh(speed),
+            SpeedUnit::KilometersPerHour => speed,
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Wind {
+    // None when the wind is variable (VRB).
+    pub direction: Option<f64>,
+    pub speed: f64,
+    pub gust: Option<f64>,
+    pub unit: SpeedUnit,
+    // Extreme directions when the wind varies, e.g. 180V240.
+    pub variable_between: Option<(f64, f64)>,
+}
+
+impl Wind {
+    pub fn speed_kmh(&self) -> f64 {
+        self.unit.to_kmh(self.speed)
+    }
+
+    pub fn gust_kmh(&self) -> Option<f64> {
+        self.gust.map(|gust| self.unit.to_kmh(gust))
+    }
+}
+
+#[derive(Debug, Clone, PartialEq)]
+pub struct Metar {
+    pub station: String,
+    // Day of month, hour and minute, in UTC.
+    pub day: u32,
+    pub hour: u32,
+    pub minute: u32,
+    pub wind: Option<Wind>,
+    pub temperature: Option<f64>,
+    pub dew_point: Option<f64>,
+    // QNH in hPa.
+    pub pressure: Option<f64>,
+}
+
+pub fn decode(report: &str) -> anyhow::Result<Metar> {
+    let mut groups = report
+        .split_whitespace()
+        .map(|group| group.trim_end_matches('='))
+        .skip_while(|group| matches!(*group, "METAR" | "SPECI" | "COR"));
+
+    let station = groups
+        .next()
+        .filter(|group| group.len() == 4 && group.chars().all(|c| c.is_ascii_alphanumeric()))
+        .ok_or_else(|| anyhow::anyhow!("Invalid station in METAR: {}", report))?
+        .to_owned();
+
+    let (day, hour, minute) = groups
+        .next()
+        .and_then(decode_time)
+        .ok_or_else(|| anyhow::anyhow!("Invalid time in METAR: {}", report))?;
+
+    let mut metar = Metar {
+        station,
+        day,
+        hour,
+        minute,
+        wind: None,
+        temperature: None,
+        dew_point: None,
+        pressure: None,
+    };
+
+    for group in groups {
+        // Remarks and trends describe something else than the current observation.
+        if matches!(group, "RMK" | "NOSIG" | "BECMG" | "TEMPO") {
+            break;
+        }
+        if metar.wind.is_none() {
+            if let Some(wind) = decode_wind(group) {
+                metar.wind = Some(wind);
+                continue;
+            }
+        }
+        if let Some(wind) = metar.wind.as_mut() {
+            if wind.variable_between.is_none() {
+                if let Some(between) = decode_variable_direction(group) {
+                    wind.variable_between = Some(between);
+                    continue;
+                }
+            }
+        }
+        if metar.temperature.is_none() {
+            if let Some((temperature, dew_point)) = decode_temperature(group) {
+                metar.temperature = Some(temperature);
+                metar.dew_point = dew_point;
+                continue;
+            }
+        }
+        if metar.pressure.is_none() {
+            if let Some(pressure) = decode_pressure(group) {
+                metar.pressure = Some(pressure);
+            }
+        }
+    }
+
+    Ok(metar)
+}
+
+// ddhhmmZ
+fn decode_time(group: &str) -> Option<(u32, u32, u32)> {
+    let digits = group.strip_suffix('Z')?;
+    if digits.len() != 6 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let day = digits[0..2].parse().ok()?;
+    let hour = digits[2..4].parse().ok()?;
+    let minute = digits[4..6].parse().ok()?;
+    Some((day, hour, minute))
+}
+
+// dddff(f)[Gff(f)]KT, VRBffKT, 00000KT. Speed may be given in MPS or KMH too.
+fn decode_wind(group: &str) -> Option<Wind> {
+    let (value, unit) = ["KT", "MPS", "KMH"].iter().find_map(|unit| {
+        group
+            .strip_suffix(unit)
+            .and_then(|value| Some((value, SpeedUnit::parse(unit)?)))
+    })?;
+
+    let direction = value.get(..3)?;
+    let speeds = value.get(3..)?;
+    let direction = match direction {
+        "VRB" => None,
+        _ if direction.chars().all(|c| c.is_ascii_digit()) => Some(direction.parse::<f64>().ok()?),
+        _ => return None,
+    };
+
+    let (speed, gust) = match speeds.split_once('G') {
+        Some((speed, gust)) => (speed, Some(gust)),
+        None => (speeds, None),
+    };
+    let parse_speed = |speed: &str| {
+        let speed = speed.strip_prefix('P').unwrap_or(speed);
+        (speed.len() >= 2 && speed.chars().all(|c| c.is_ascii_digit()))
+            .then(|| speed.parse::<f64>().ok())
+            .flatten()
+    };
+    let speed = parse_speed(speed)?;
+    let gust = match gust {
+        Some(gust) => Some(parse_speed(gust)?),
+        None => None,
+    };
+
+    Some(Wind {
+        direction: direction.filter(|_| speed != 0.0),
+        speed,
+        gust,
+        unit,
+        variable_between: None,
+    })
+}
+
+// dddVddd
+fn decode_variable_direction(group: &str) -> Option<(f64, f64)> {
+    let (from, to) = group.split_once('V')?;
+    if from.len() != 3 || to.len() != 3 {
+        return None;
+    }
+    Some((from.parse().ok()?, to.parse().ok()?))
+}
+
+// Decodes values like "12", "M05".
+fn decode_signed(value: &str) -> Option<f64> {
+    let (sign, digits) = match value.strip_prefix('M') {
+        Some(digits) => (-1.0, digits),
+        None => (1.0, value),
+    };
+    if digits.len() != 2 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    Some(sign * digits.parse::<f64>().ok()?)
+}
+
+// TT/DD, e.g. "21/15", "M02/M05", "12/".
+fn decode_temperature(group: &str) -> Option<(f64, Option<f64>)> {
+    let (temperature, dew_point) = group.split_once('/')?;
+    let temperature = decode_signed(temperature)?;
+    let dew_point = decode_signed(dew_point);
+    Some((temperature, dew_point))
+}
+
+// Q1013 (hPa) or A2992 (inHg).
+fn decode_pressure(group: &str) -> Option<f64> {
+    let kind = group.get(..1)?;
+    let digits = group.get(1..)?;
+    if digits.len() != 4 || !digits.chars().all(|c| c.is_ascii_digit()) {
+        return None;
+    }
+    let value = digits.parse::<f64>().ok()?;
+    match kind {
+        "Q" => Some(value),
+        "A" => Some(inhg_to_hpa(value / 100.0).round()),
+        _ => None,
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn wind(group: &str) -> Wind {
+        decode_wind(group).unwrap_or_else(|| panic!("wind expected in {}", group))
+    }
+
+    #[test]
+    fn decode_wind_variable() {
+        let wind = wind("VRB03KT");
+        assert_eq!(wind.direction, None);
+        assert_eq!(wind.speed, 3.0);
+        assert_eq!(wind.unit, SpeedUnit::Knots);
+    }
+
+    #[test]
+    fn decode_wind_calm() {
+        let wind = wind("00000KT");
+        assert_eq!(wind.direction, None);
+        assert_eq!(wind.speed, 0.0);
+        assert_eq!(wind.gust, None);
+    }
+
+    #[test]
+    fn decode_wind_with_gusts() {
+        let wind = wind("24015G25KT");
+        assert_eq!(wind.direction, Some(240.0));
+        assert_eq!((wind.speed, wind.gust), (15.0, Some(25.0)));
+        assert!((wind.speed_kmh() - 27.78).abs() < 0.01);
+        assert!((wind.gust_kmh().unwrap() - 46.3).abs() < 0.01);
+    }
+
+    #[test]
+    fn decode_wind_above_limit() {
+        let wind = wind("240P49MPS");
+        assert_eq!(wind.direction, Some(240.0));
+        assert_eq!(wind.speed, 49.0);
+        assert_eq!(wind.unit, SpeedUnit::MetersPerSecond);
+        assert!((wind.speed_kmh() - 176.4).abs() < 0.01);
+    }
+
+    #[test]
+    fn decode_wind_rejects_other_groups() {
+        assert_eq!(decode_wind("9999"), None);
+        assert_eq!(decode_wind("FEW030"), None);
+        assert_eq!(decode_wind("24015XKT"), None);
+    }
+
+    #[test]
+    fn decode_variable_direction_range() {
+        assert_eq!(decode_variable_direction("180V240"), Some((180.0, 240.0)));
+        assert_eq!(decode_variable_direction("18V240"), None);
+    }
+
+    #[test]
+    fn decode_temperature_below_zero() {
+        assert_eq!(decode_temperature("M02/M05"), Some((-2.0, Some(-5.0))));
+        assert_eq!(decode_temperature("21/15"), Some((21.0, Some(15.0))));
+    }
+
+    #[test]
+    fn decode_temperature_without_dew_point() {
+        assert_eq!(decode_temperature("12/"), Some((12.0, None)));
+        assert_eq!(decode_temperature("/12"), None);
+    }
+
+    #[test]
+    fn decode_pressure_units() {
+        assert_eq!(decode_pressure("Q1013"), Some(1013.0));
+        assert_eq!(decode_pressure("A2992"), Some(1013.0));
+        assert_eq!(decode_pressure("Q101"), None);
+    }
+
+    #[test]
+    fn decode_full_report() {
+        let metar =
+            decode("METAR LEBL 121030Z 24015G25KT 180V240 9999 FEW030 M02/M05 Q1013 NOSIG=")
+                .unwrap();
+        assert_eq!(metar.station, "LEBL");
+        assert_eq!((metar.day, metar.hour, metar.minute), (12, 10, 30));
+        let wind = metar.wind.unwrap();
+        assert_eq!(wind.direction, Some(240.0));
+        assert_eq!(wind.variable_between, Some((180.0, 240.0)));
+        assert_eq!(metar.temperature, Some(-2.0));
+        assert_eq!(metar.dew_point, Some(-5.0));
+        assert_eq!(metar.pressure, Some(1013.0));
+    }
+
+    #[test]
+    fn decode_stops_at_trends_and_remarks() {
+        let metar = decode("LEGE 121000Z 9999 TEMPO 30025G35KT 15/10 Q1020").unwrap();
+        assert_eq!(metar.wind, None);
+        assert_eq!(metar.temperature, None);
+        assert_eq!(metar.pressure, None);
+
+        let metar = decode("KJFK 121051Z 31008KT 10SM 12/ A2992 RMK AO2 SLP132 T01220067").unwrap();
+        assert_eq!(metar.wind.unwrap().speed, 8.0);
+        assert_eq!(metar.temperature, Some(12.0));
+        assert_eq!(metar.dew_point, None);
+        assert_eq!(metar.pressure, Some(1013.0));
+    }
+
+    #[test]
+    fn decode_rejects_invalid_header() {
+        assert!(decode("LEBL 24015KT").is_err());
+        assert!(decode("").is_err());
+    }
+}
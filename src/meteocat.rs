@@ -1,25 +1,33 @@
-use crate::common::{parse_selector, Measurement, Station};
+use crate::common::{parse_selector, station_id_of, Measurement, Station};
 
 use anyhow::anyhow;
 use encoding_rs::UTF_8;
 use scraper::Html;
 use serde::Deserialize;
 use spin_sdk::http::{Method, Request, Response};
-use std::collections::HashMap;
+use spin_sdk::key_value::Store;
+use std::collections::{BTreeSet, HashMap};
 
 const BASE_URL: &str = "https://www.meteo.cat/observacions/xema";
+const UNKNOWN_KEYS_STORE_KEY: &str = "meteocat:unknown_keys";
 
 #[allow(non_snake_case)]
 #[derive(Debug, Deserialize)]
 struct MeasurementRaw {
     temperatura: Option<f64>,
-    // humitat: Option<f64>,
-    // precipitacio: Option<f64>,
+    humitat: Option<f64>,
+    precipitacio: Option<f64>,
+    pressioAtmosferica: Option<f64>,
+    irradianciaSolarGlobal: Option<f64>,
     velocitatVent: Option<f64>,
     direccioVent: Option<f64>,
-    // alturaSensorVent: Option<u64>,
+    alturaSensorVent: Option<f64>,
     ratxaMaximaVent: Option<f64>,
-    // direccioRatxaMaximaVent: Option<f64>,
+    direccioRatxaMaximaVent: Option<f64>,
+
+    // Anything that XEMA adds in the future ends up here.
+    #[serde(flatten)]
+    unknown: HashMap<String, serde_json::Value>,
 }
 
 #[derive(Debug, Deserialize)]
@@ -38,7 +46,8 @@ struct StacionRaw {
 type MeasurementsRaw = HashMap<String, HashMap<String, MeasurementRaw>>;
 type StationsRaw = HashMap<String, StacionRaw>;
 
-pub async fn fetch_data() -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+// Unknown keys are only reported on refresh, read requests don't change any data.
+pub async fn fetch_data(refresh: bool) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
     println!("[meteocat] Fetching...");
     let request = Request::builder().method(Method::Get).uri(BASE_URL).build();
 
@@ -48,8 +57,20 @@ pub async fn fetch_data() -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
         println!("Decoding errors found");
     }
 
+    let (stations, measurements, unknown_keys) = parse_page(&body)?;
+    if refresh {
+        if let Err(e) = report_unknown_keys(unknown_keys) {
+            println!("[meteocat] Reporting unknown keys failed: {}", e);
+        }
+    }
+    Ok((stations, measurements))
+}
+
+// Returns the stations with their latest measurements, and the keys of the
+// measurements that aren't known.
+fn parse_page(body: &str) -> anyhow::Result<(Vec<Station>, Vec<Measurement>, BTreeSet<String>)> {
     println!("Parsing...");
-    let document = Html::parse_document(&body);
+    let document = Html::parse_document(body);
 
     println!("Analyzing...");
     let script_selector = parse_selector("script")?;
@@ -90,6 +111,11 @@ pub async fn fetch_data() -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
         .last()
         .ok_or_else(|| anyhow!("Empty list of measurements"))?;
 
+    let unknown_keys: BTreeSet<String> = last_measurements_raw
+        .values()
+        .flat_map(|measurement_raw| measurement_raw.unknown.keys().cloned())
+        .collect();
+
     let mut available_stations: Vec<Station> = vec![];
     let mut measurements: Vec<Measurement> = vec![];
 
@@ -97,23 +123,38 @@ pub async fn fetch_data() -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
         if let Some(wind_speed) = measurement_raw.velocitatVent {
             if let Some(station_raw) = stations_raw.get(vendor_id) {
                 let station_url = format!("{}/dades?codi={}", BASE_URL, vendor_id);
-                let station_id = format!("{:x}", md5::compute(&station_url));
+                let station_id = station_id_of("meteocat", vendor_id);
                 let measurement = Measurement {
                     station_id: station_id.clone(),
                     wind_speed: wind_speed.round() as u64,
                     wind_direction: measurement_raw.direccioVent,
                     gusts_speed: measurement_raw.ratxaMaximaVent.map(|v| v.round() as u64),
+                    gusts_direction: measurement_raw.direccioRatxaMaximaVent,
+                    wind_sensor_height: measurement_raw.alturaSensorVent,
+                    daily_max_wind: None,
+                    daily_max_wind_since: None,
                     temperature: measurement_raw.temperatura,
+                    humidity: measurement_raw.humitat,
+                    pressure: measurement_raw.pressioAtmosferica,
+                    precipitation: measurement_raw.precipitacio,
+                    irradiance: measurement_raw.irradianciaSolarGlobal,
                     last_update: last_timestamp.to_string(),
+                    stale: false,
+                    qc_flags: Default::default(),
+                    wind_details: None,
+                    derived: None,
                 };
                 let station = Station {
                     id: station_id,
                     name: station_raw.nom.to_string(),
-                    elevation: station_raw.altitud.round() as i64,
+                    elevation: Some(station_raw.altitud.round() as i64),
                     url: station_url,
                     lat: station_raw.coordenades.latitud,
                     long: station_raw.coordenades.longitud,
                     available: true,
+                    provider: "meteocat".to_owned(),
+                    vendor_id: vendor_id.to_string(),
+                    alternates: vec![],
                 };
                 available_stations.push(station);
                 measurements.push(measurement);
@@ -123,5 +164,81 @@ pub async fn fetch_data() -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
         }
     }
 
-    Ok((available_stations, measurements))
+    Ok((available_stations, measurements, unknown_keys))
+}
+
+// Unknown keys are reported once, the ones already seen are kept in the store.
+fn report_unknown_keys(unknown_keys: BTreeSet<String>) -> anyhow::Result<()> {
+    if unknown_keys.is_empty() {
+        return Ok(());
+    }
+    let store = Store::open_default()?;
+    let mut reported = store
+        .get_json::<BTreeSet<String>>(UNKNOWN_KEYS_STORE_KEY)?
+        .unwrap_or_default();
+    let new_keys = unknown_keys
+        .into_iter()
+        .filter(|key| !reported.contains(key))
+        .collect::<Vec<String>>();
+    if new_keys.is_empty() {
+        return Ok(());
+    }
+    for key in new_keys {
+        println!("[meteocat] Unknown measurement key: {}", key);
+        reported.insert(key);
+    }
+    store.set_json(UNKNOWN_KEYS_STORE_KEY, &reported)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn parse_fixture() -> (Vec<Station>, Vec<Measurement>, BTreeSet<String>) {
+        parse_page(include_str!("../tests/fixtures/meteocat_xema.html")).unwrap()
+    }
+
+    #[test]
+    fn parse_page_takes_latest_measurements() {
+        let (stations, measurements, _) = parse_fixture();
+        // Station without wind and station without details are skipped.
+        assert_eq!(stations.len(), 1);
+        assert_eq!(measurements.len(), 1);
+
+        let station = &stations[0];
+        assert_eq!(station.id, station_id_of("meteocat", "X4"));
+        assert_eq!(station.name, "Barcelona - el Raval");
+        assert_eq!(
+            station.url,
+            "https://www.meteo.cat/observacions/xema/dades?codi=X4"
+        );
+        assert_eq!(station.elevation, Some(33));
+        assert_eq!((station.lat, station.long), (41.38390, 2.16775));
+
+        let measurement = &measurements[0];
+        assert_eq!(measurement.station_id, station.id);
+        assert_eq!(measurement.last_update, "2024-05-12T10:00Z");
+        assert_eq!(measurement.wind_speed, 13);
+        assert_eq!(measurement.wind_direction, Some(203.0));
+        assert_eq!(measurement.gusts_speed, Some(25));
+        assert_eq!(measurement.gusts_direction, Some(215.0));
+        assert_eq!(measurement.wind_sensor_height, Some(6.0));
+        assert_eq!(measurement.temperature, Some(19.4));
+        assert_eq!(measurement.humidity, Some(62.0));
+        assert_eq!(measurement.precipitation, Some(0.2));
+        assert_eq!(measurement.pressure, Some(1014.6));
+        assert_eq!(measurement.irradiance, Some(642.0));
+    }
+
+    #[test]
+    fn parse_page_collects_unknown_keys() {
+        let (_, _, unknown_keys) = parse_fixture();
+        let unknown_keys: Vec<&str> = unknown_keys.iter().map(|key| key.as_str()).collect();
+        assert_eq!(unknown_keys, ["gruixNeu"]);
+    }
+
+    #[test]
+    fn parse_page_requires_data() {
+        assert!(parse_page("<html><body></body></html>").is_err());
+    }
 }
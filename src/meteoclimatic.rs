@@ -1,41 +1,155 @@
-use crate::common::{parse_selector, wind_direction_to_degrees, Measurement, Station};
+use crate::calibration::{Calibration, ReferencePoint};
+use crate::common::{
+    parse_last_update, parse_selector, station_id_of, wind_direction_to_degrees, Measurement,
+    Station,
+};
+use crate::history::History;
+use crate::meteoclimatic_profile::{Profile, ProfileCache};
 use anyhow::anyhow;
-use chrono::{Duration, NaiveTime, TimeZone, Utc};
+use chrono::{DateTime, Duration, NaiveTime, Utc};
 use encoding_rs::ISO_8859_15;
 use html_escape::decode_html_entities;
-use scraper::{ElementRef, Html, Node};
+use scraper::{ElementRef, Html, Node, Selector};
+use serde::Deserialize;
 use spin_sdk::http::{Method, Request, Response};
 use std::collections::HashMap;
 
-// Two reference points has been selected to convert from
-// pixel location (x, y) to geolocation (long, lat).
-
-// ESCAT0800000008572A: Bellmunt
-const X1: f64 = 394.0;
-const Y1: f64 = 223.0;
-const LAT1: f64 = 42.10178106107319;
-const LONG1: f64 = 2.294541510472325;
-
-//ESCAT0800000008870D: Sitges
-const X2: f64 = 309.0;
-const Y2: f64 = 416.0;
-const LAT2: f64 = 41.235099892573196;
-const LONG2: f64 = 1.8118575503754906;
-
-const XM: f64 = (LONG1 - LONG2) / (X1 - X2);
-const XC: f64 = (X1 * LONG2 - X2 * LONG1) / (X1 - X2);
-const YM: f64 = (LAT1 - LAT2) / (Y1 - Y2);
-const YC: f64 = (Y1 * LAT2 - Y2 * LAT1) / (Y1 - Y2);
-
-fn xy_to_long_lat(x: f64, y: f64) -> (f64, f64) {
-    let long = XM * x + XC;
-    let lat = YM * y + YC;
-    (long, lat)
+// Stations of known geolocation used to convert from pixel location (x, y)
+// on the map of a region to geolocation (long, lat). Their pixel location is
+// taken from the map itself, so that calibration follows changes of the map image.
+// Stations with cached profiles are used as reference points too, and take
+// precedence. Besides Bellmunt and Sitges, stations are the ones of towns
+// spread over the maps, at the coordinates of the towns. Reference stations
+// missing on the map are skipped and the ones far off are dropped by the fit.
+const ESCAT_REFERENCE_STATIONS: &[(&str, f64, f64)] = &[
+    // Bellmunt
+    ("ESCAT0800000008572A", 42.10178106107319, 2.294541510472325),
+    // Sitges
+    (
+        "ESCAT0800000008870D",
+        41.235099892573196,
+        1.8118575503754906,
+    ),
+    // Girona
+    ("ESCAT1700000017001A", 41.9794, 2.8214),
+    // Figueres
+    ("ESCAT1700000017600A", 42.2670, 2.9610),
+    // Lleida
+    ("ESCAT2500000025001A", 41.6176, 0.6200),
+    // La Seu d'Urgell
+    ("ESCAT2500000025700A", 42.3584, 1.4613),
+    // Tarragona
+    ("ESCAT4300000043001A", 41.1189, 1.2445),
+    // Tortosa
+    ("ESCAT4300000043500A", 40.8126, 0.5216),
+];
+
+const ESARA_REFERENCE_STATIONS: &[(&str, f64, f64)] = &[
+    // Huesca
+    ("ESARA2200000022001A", 42.1401, -0.4089),
+    // Jaca
+    ("ESARA2200000022700A", 42.5700, -0.5497),
+    // Teruel
+    ("ESARA4400000044001A", 40.3456, -1.1065),
+    // Alcañiz
+    ("ESARA4400000044600A", 41.0511, -0.1334),
+    // Zaragoza
+    ("ESARA5000000050001A", 41.6488, -0.8891),
+    // Calatayud
+    ("ESARA5000000050300A", 41.3533, -1.6433),
+];
+
+const ESPVA_REFERENCE_STATIONS: &[(&str, f64, f64)] = &[
+    // Alacant
+    ("ESPVA0300000003001A", 38.3452, -0.4810),
+    // Elx
+    ("ESPVA0300000003201A", 38.2669, -0.6983),
+    // Castelló de la Plana
+    ("ESPVA1200000012001A", 39.9864, -0.0513),
+    // Morella
+    ("ESPVA1200000012300A", 40.6190, -0.1006),
+    // València
+    ("ESPVA4600000046001A", 39.4699, -0.3763),
+    // Requena
+    ("ESPVA4600000046340A", 39.4883, -1.1004),
+];
+
+// Placement of the stations on the map of Catalonia used before the map
+// was calibrated, from the pixel locations of Bellmunt and Sitges. It's kept
+// for when the reference stations aren't enough to calibrate the map.
+const ESCAT_PLACEMENT: &[ReferencePoint] = &[
+    ReferencePoint {
+        x: 394.0,
+        y: 223.0,
+        lat: 42.10178106107319,
+        long: 2.294541510472325,
+    },
+    ReferencePoint {
+        x: 309.0,
+        y: 416.0,
+        lat: 41.235099892573196,
+        long: 1.8118575503754906,
+    },
+];
+
+fn placement(region: &str) -> &'static [ReferencePoint] {
+    match region {
+        "ESCAT" => ESCAT_PLACEMENT,
+        _ => &[],
+    }
+}
+
+#[derive(Debug, Deserialize)]
+struct ReferenceStationRaw {
+    vendor_id: String,
+    lat: f64,
+    long: f64,
+}
+
+// Built-in reference stations of the region together with the ones of the
+// `meteoclimatic_reference_stations` variable, which take precedence.
+fn reference_stations(region: &str) -> anyhow::Result<HashMap<String, (f64, f64)>> {
+    let built_in = match region {
+        "ESCAT" => ESCAT_REFERENCE_STATIONS,
+        "ESARA" => ESARA_REFERENCE_STATIONS,
+        "ESPVA" => ESPVA_REFERENCE_STATIONS,
+        _ => &[],
+    };
+    let mut references = built_in
+        .iter()
+        .map(|(vendor_id, lat, long)| (vendor_id.to_string(), (*lat, *long)))
+        .collect::<HashMap<String, (f64, f64)>>();
+
+    let config = spin_sdk::variables::get("meteoclimatic_reference_stations")?;
+    if !config.trim().is_empty() {
+        let configured: Vec<ReferenceStationRaw> = serde_json::from_str(&config)?;
+        for station in configured {
+            references.insert(station.vendor_id, (station.lat, station.long));
+        }
+    }
+    Ok(references)
 }
 
-pub async fn fetch_data() -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
-    println!("[meteoclimatic] Fetching...");
-    let url = "https://www.meteoclimatic.net/mapinfo/ESCAT";
+// Region codes, as used by https://www.meteoclimatic.net/mapinfo/<region>
+pub fn regions() -> anyhow::Result<Vec<String>> {
+    let regions = spin_sdk::variables::get("meteoclimatic_regions")?
+        .split(',')
+        .map(|region| region.trim().to_uppercase())
+        .filter(|region| !region.is_empty())
+        .collect();
+    Ok(regions)
+}
+
+type Pixels = HashMap<String, (f64, f64)>;
+
+// Profile pages are only fetched when enrich_profiles is set, i.e. in the
+// refresh pass, so that reads don't depend on them.
+pub async fn fetch_data(
+    region: &str,
+    enrich_profiles: bool,
+) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+    println!("[meteoclimatic/{}] Fetching...", region);
+    let url = format!("https://www.meteoclimatic.net/mapinfo/{}", region);
 
     let request = Request::builder().method(Method::Get).uri(url).build();
 
@@ -50,10 +164,50 @@ pub async fn fetch_data() -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
 
     println!("Analyzing...");
     let mut stations: HashMap<String, Station> = HashMap::new();
-    collect_stations(&document, &mut stations)?;
+    let mut pixels: Pixels = HashMap::new();
+    collect_stations(&document, &mut stations, &mut pixels)?;
+
+    let mut profile_cache = match ProfileCache::open() {
+        Ok(profile_cache) => Some(profile_cache),
+        Err(e) => {
+            println!("Profile cache unavailable: {}", e);
+            None
+        }
+    };
+    let profiles = profile_cache
+        .as_ref()
+        .map(|profile_cache| profile_cache.profiles())
+        .unwrap_or_default();
+
+    let references = reference_stations(region)?;
+    locate_stations(
+        &mut stations,
+        &pixels,
+        &references,
+        &profiles,
+        placement(region),
+    );
 
     let measurements = collect_measurements(document, &mut stations)?;
 
+    // Profile pages are more accurate than the tooltips.
+    for (href, station) in stations.iter_mut() {
+        if let Some(profile) = vendor_id_of(href).and_then(|vendor_id| profiles.get(vendor_id)) {
+            station.elevation = Some(profile.elevation);
+        }
+    }
+
+    if let Some(profile_cache) = profile_cache.as_mut().filter(|_| enrich_profiles) {
+        let mut vendor_ids = pixels
+            .keys()
+            .filter_map(|href| vendor_id_of(href).map(|vendor_id| vendor_id.to_owned()))
+            .collect::<Vec<String>>();
+        vendor_ids.sort();
+        if let Err(e) = profile_cache.enrich(&vendor_ids).await {
+            println!("Enriching profiles failed: {}", e);
+        }
+    }
+
     let stations_count = stations.len();
 
     let available_stations: Vec<Station> = stations
@@ -73,6 +227,7 @@ pub async fn fetch_data() -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
 fn collect_stations(
     document: &Html,
     stations: &mut HashMap<String, Station>,
+    pixels: &mut Pixels,
 ) -> Result<(), anyhow::Error> {
     let map_selector = parse_selector("map#estacions")?;
     let point_selector = parse_selector("area")?;
@@ -82,7 +237,7 @@ fn collect_stations(
         .ok_or(anyhow!("Stations map not found"))?;
 
     stations_map.select(&point_selector).for_each(|area| {
-        if let Err(e) = consume_area(area, stations) {
+        if let Err(e) = consume_area(area, stations, pixels) {
             println!("{}", e);
         }
     });
@@ -93,6 +248,7 @@ fn collect_stations(
 fn consume_area(
     area: ElementRef<'_>,
     stations: &mut HashMap<String, Station>,
+    pixels: &mut Pixels,
 ) -> anyhow::Result<()> {
     let shape = area.attr("shape").unwrap_or("");
     if shape == "circle" {
@@ -129,47 +285,326 @@ fn consume_area(
             })?;
 
         let url = format!("https://www.meteoclimatic.net{}", href);
-        let id = format!("{:x}", md5::compute(&url));
-        let (long, lat) = xy_to_long_lat(x, y);
+        let id = station_id_of("meteoclimatic", vendor_id_of(href).unwrap_or(href));
 
         let entry = Station {
             id: id.to_owned(),
             name: "".to_owned(),
-            elevation: 0,
+            elevation: None,
             available: false,
+            provider: "meteoclimatic".to_owned(),
+            vendor_id: vendor_id_of(href).unwrap_or(href).to_owned(),
+            alternates: vec![],
             url,
-            lat,
-            long,
+            lat: 0.0,
+            long: 0.0,
         };
         if stations.contains_key(href) {
             anyhow::bail!("Duplicate station href found: {}", href);
         }
         stations.insert(href.to_owned(), entry);
+        pixels.insert(href.to_owned(), (x, y));
     }
     Ok(())
 }
 
+fn vendor_id_of(href: &str) -> Option<&str> {
+    href.strip_prefix("/perfil/")
+}
+
+// Three points fit the affine transform exactly, so at least one more is
+// needed for the residual error to tell whether the calibration is right.
+const MIN_CALIBRATION_POINTS: usize = 4;
+// Reference points further than this from the location converted by the
+// fit of the other points are dropped, the worst first.
+const MAX_REFERENCE_ERROR_M: f64 = 3000.0;
+const MAX_CALIBRATION_RMS_ERROR_M: f64 = 2000.0;
+
+fn calibrate(mut points: Vec<(&str, ReferencePoint)>) -> anyhow::Result<Calibration> {
+    loop {
+        if points.len() < MIN_CALIBRATION_POINTS {
+            anyhow::bail!(
+                "At least {} reference points required, {} found",
+                MIN_CALIBRATION_POINTS,
+                points.len()
+            );
+        }
+        let reference_points = points.iter().map(|(_, p)| *p).collect::<Vec<_>>();
+        let calibration = Calibration::fit(&reference_points)?;
+        if calibration.max_error_m <= MAX_REFERENCE_ERROR_M {
+            if calibration.rms_error_m > MAX_CALIBRATION_RMS_ERROR_M {
+                anyhow::bail!(
+                    "Residual error {:.0} m RMS above {:.0} m",
+                    calibration.rms_error_m,
+                    MAX_CALIBRATION_RMS_ERROR_M
+                );
+            }
+            return Ok(calibration);
+        }
+        // A wrong point pulls the fit towards itself, so each point is
+        // checked against the fit of the others.
+        let (worst, error_m) = (0..reference_points.len())
+            .map(|i| {
+                let mut others = reference_points.clone();
+                let point = others.remove(i);
+                let error_m = Calibration::fit(&others)
+                    .map(|calibration| calibration.error_m(&point))
+                    .unwrap_or_default();
+                (i, error_m)
+            })
+            .max_by(|(_, a), (_, b)| a.total_cmp(b))
+            .unwrap_or_default();
+        let (vendor_id, _) = points.remove(worst);
+        println!(
+            "[{}] Reference station dropped, {:.0} m off the calibration of the others",
+            vendor_id, error_m
+        );
+    }
+}
+
+// Stations are located by their profiles, else by the calibration of the map.
+// Until the map can be calibrated, they keep the built-in placement of the
+// region, or the one of the few reference points found.
+fn locate_stations(
+    stations: &mut HashMap<String, Station>,
+    pixels: &Pixels,
+    references: &HashMap<String, (f64, f64)>,
+    profiles: &HashMap<String, Profile>,
+    placement: &[ReferencePoint],
+) {
+    let mut references: HashMap<&str, (f64, f64)> = references
+        .iter()
+        .map(|(vendor_id, location)| (vendor_id.as_str(), *location))
+        .collect();
+    for (vendor_id, profile) in profiles {
+        if pixels.contains_key(&format!("/perfil/{}", vendor_id)) {
+            references.insert(vendor_id, (profile.lat, profile.long));
+        }
+    }
+
+    let reference_points = references
+        .iter()
+        .filter_map(|(vendor_id, (lat, long))| {
+            let href = format!("/perfil/{}", vendor_id);
+            if let Some((x, y)) = pixels.get(&href) {
+                let point = ReferencePoint {
+                    x: *x,
+                    y: *y,
+                    lat: *lat,
+                    long: *long,
+                };
+                Some((*vendor_id, point))
+            } else {
+                println!("[{}] Reference station not found on the map", vendor_id);
+                None
+            }
+        })
+        .collect::<Vec<(&str, ReferencePoint)>>();
+
+    let fallback_points = if placement.is_empty() {
+        reference_points.iter().map(|(_, p)| *p).collect()
+    } else {
+        placement.to_vec()
+    };
+    let calibration = match calibrate(reference_points) {
+        Ok(calibration) => {
+            println!(
+                "Map calibrated with {} points, residual error: {:.0} m RMS, {:.0} m max",
+                calibration.points_count, calibration.rms_error_m, calibration.max_error_m
+            );
+            Some(calibration)
+        }
+        Err(e) => {
+            println!("Map not calibrated: {}", e);
+            match Calibration::fit(&fallback_points) {
+                Ok(calibration) => {
+                    println!(
+                        "Stations placed by {} points until the map is calibrated",
+                        calibration.points_count
+                    );
+                    Some(calibration)
+                }
+                Err(e) => {
+                    println!("Only stations with profiles located: {}", e);
+                    None
+                }
+            }
+        }
+    };
+
+    stations.retain(|href, station| {
+        if let Some(profile) = vendor_id_of(href).and_then(|vendor_id| profiles.get(vendor_id)) {
+            station.long = profile.long;
+            station.lat = profile.lat;
+            true
+        } else if let (Some((x, y)), Some(calibration)) = (pixels.get(href), calibration) {
+            let (long, lat) = calibration.xy_to_long_lat(*x, *y);
+            station.long = long;
+            station.lat = lat;
+            true
+        } else {
+            false
+        }
+    });
+}
+
 fn collect_measurements(
     document: Html,
     stations: &mut HashMap<String, Station>,
 ) -> Result<Vec<Measurement>, anyhow::Error> {
     let tooltip_selector = parse_selector("span.tooltip")?;
     let mut measurements: Vec<Measurement> = Vec::new();
+    let utc_now = Utc::now();
 
     document.select(&tooltip_selector).for_each(|span| {
-        if let Err(e) = consume_span(span, stations, &mut measurements) {
+        if let Err(e) = consume_span(span, stations, &mut measurements, utc_now) {
             println!("{}", e);
         }
     });
     Ok(measurements)
 }
 
+// Rows of the tooltip table are recognized by their captions rather than
+// by position, so that reordering upstream doesn't mix up the readings.
+#[derive(Debug, Clone, Copy, PartialEq)]
+enum RowLabel {
+    LastUpdate,
+    Temperature,
+    Humidity,
+    Pressure,
+    Precipitation,
+    Wind,
+    DailyMaxWind,
+}
+
+// Caption of a row is given by its first cell or by the icon in it, its
+// value by the other cells, e.g. "Humedad" and "48 %".
+#[derive(Debug, PartialEq)]
+struct Row {
+    caption: String,
+    value: String,
+}
+
+fn collect_row(row: ElementRef<'_>, cell_selector: &Selector, img_selector: &Selector) -> Row {
+    let text_of = |element: ElementRef<'_>| element.text().collect::<String>().trim().to_owned();
+    let cells: Vec<ElementRef<'_>> = row.select(cell_selector).collect();
+
+    let mut caption = row
+        .select(img_selector)
+        .filter_map(|img| img.attr("alt").or_else(|| img.attr("title")))
+        .collect::<Vec<&str>>()
+        .join(" ");
+    let mut value_cells = &cells[..];
+    if let [first, rest @ ..] = &cells[..] {
+        let first = text_of(*first);
+        if !rest.is_empty() && !first.is_empty() && !first.contains(|c: char| c.is_ascii_digit()) {
+            caption = format!("{} {}", caption, first);
+            value_cells = rest;
+        }
+    }
+
+    let value = if cells.is_empty() {
+        text_of(row)
+    } else {
+        value_cells
+            .iter()
+            .map(|cell| text_of(*cell))
+            .filter(|text| !text.is_empty())
+            .collect::<Vec<String>>()
+            .join(" ")
+    };
+    Row {
+        caption: caption.trim().to_owned(),
+        value,
+    }
+}
+
+fn label_row(row: &Row) -> Option<RowLabel> {
+    if row.value.starts_with("Actualizado:") {
+        return Some(RowLabel::LastUpdate);
+    }
+    if row.caption.is_empty() {
+        return label_row_by_unit(&row.value);
+    }
+    let caption = row.caption.to_lowercase();
+    let has_any = |words: &[&str]| words.iter().any(|word| caption.contains(word));
+    let is_extreme = has_any(&["máx", "max", "mín", "min"]);
+    // Dew point, wind chill and the extremes of the day aren't the current readings.
+    if has_any(&["rocío", "rocio", "sensación", "sensacion"]) {
+        None
+    } else if has_any(&["temperatura"]) {
+        (!is_extreme).then_some(RowLabel::Temperature)
+    } else if has_any(&["humedad"]) {
+        (!is_extreme).then_some(RowLabel::Humidity)
+    } else if has_any(&["presión", "presion", "barómetro", "barometro"]) {
+        (!is_extreme).then_some(RowLabel::Pressure)
+    } else if has_any(&["precipitación", "precipitacion", "lluvia"]) {
+        Some(RowLabel::Precipitation)
+    } else if has_any(&["racha"]) || has_any(&["viento"]) && is_extreme {
+        Some(RowLabel::DailyMaxWind)
+    } else if has_any(&["viento"]) {
+        Some(RowLabel::Wind)
+    } else {
+        None
+    }
+}
+
+// Rows without a caption are recognized by the units of their values.
+fn label_row_by_unit(value: &str) -> Option<RowLabel> {
+    if value.contains("°C") {
+        Some(RowLabel::Temperature)
+    } else if value == "Calma" {
+        Some(RowLabel::Wind)
+    } else if value.contains("km/h") {
+        Some(label_wind_row(value))
+    } else if value.contains("hPa") {
+        Some(RowLabel::Pressure)
+    } else if value.contains("mm") {
+        Some(RowLabel::Precipitation)
+    } else if value.contains('%') {
+        Some(RowLabel::Humidity)
+    } else {
+        None
+    }
+}
+
+// The current wind comes with a direction, while the max wind speed of the
+// day is labelled as such or given without a direction. A speed of zero
+// has no direction either, it's taken as the current wind.
+fn label_wind_row(row: &str) -> RowLabel {
+    let lowercase = row.to_lowercase();
+    if lowercase.contains("máx") || lowercase.contains("max") {
+        return RowLabel::DailyMaxWind;
+    }
+    let parts = wind_parts(row);
+    let has_direction = parts
+        .get(2)
+        .and_then(|s| wind_direction_to_degrees(s))
+        .is_some();
+    let is_zero = parts.first().and_then(|s| s.parse::<u64>().ok()) == Some(0);
+    if has_direction || is_zero {
+        RowLabel::Wind
+    } else {
+        RowLabel::DailyMaxWind
+    }
+}
+
+// Words of a wind row from the speed on, e.g. ["25", "km/h"] for "Máx: 25 km/h".
+fn wind_parts(row: &str) -> Vec<&str> {
+    row.split_whitespace()
+        .skip_while(|s| s.parse::<u64>().is_err())
+        .collect()
+}
+
 fn consume_span(
     span: ElementRef<'_>,
     stations: &mut HashMap<String, Station>,
     measurements: &mut Vec<Measurement>,
+    utc_now: DateTime<Utc>,
 ) -> anyhow::Result<()> {
     let row_selector = parse_selector("tr")?;
+    let cell_selector = parse_selector("td")?;
+    let img_selector = parse_selector("img")?;
 
     let vendor_id = if let Some(vendor_id) = span.attr("id") {
         vendor_id
@@ -182,7 +617,7 @@ fn consume_span(
         match collect_station_info(span) {
             Ok((name, altitude)) => {
                 station.name = name;
-                station.elevation = altitude;
+                station.elevation = Some(altitude);
             }
             Err(e) => {
                 anyhow::bail!("[{}]: {}", vendor_id, e);
@@ -196,30 +631,53 @@ fn consume_span(
 
         let rows = span
             .select(&row_selector)
-            .map(|row| row.text().collect::<Vec<&str>>().join("").trim().to_owned())
-            .collect::<Vec<String>>();
+            .map(|row| collect_row(row, &cell_selector, &img_selector))
+            .collect::<Vec<Row>>();
 
-        if let Some(timestamp) = rows.get(1) {
+        let find_row = |label: RowLabel| {
+            rows.iter()
+                .find(|row| label_row(row) == Some(label))
+                .map(|row| &row.value)
+        };
+
+        let last_update = if let Some(timestamp) = find_row(RowLabel::LastUpdate) {
             match collect_last_update_utc(timestamp.to_owned()) {
-                Ok(last_update_utc) => {
-                    let last_update = parse_time_utc(&last_update_utc)?;
-                    measurement.last_update = last_update.format("%Y-%m-%dT%H:%MZ").to_string();
-                }
+                Ok(last_update_utc) => parse_time_utc(&last_update_utc, utc_now)?,
                 Err(e) => {
                     anyhow::bail!("[{}]: {}", vendor_id, e);
                 }
             }
         } else {
-            anyhow::bail!("[{}]: Wind information not available", vendor_id);
-        }
+            anyhow::bail!("[{}]: Last update not available", vendor_id);
+        };
+        measurement.last_update = last_update.format("%Y-%m-%dT%H:%MZ").to_string();
 
-        if let Some(temp) = rows.get(2) {
+        if let Some(temp) = find_row(RowLabel::Temperature) {
             if let Ok(temperature) = collect_temp_info(temp.to_owned()) {
                 measurement.temperature = Some(temperature);
             }
         }
 
-        if let Some(wind) = rows.get(4) {
+        if let Some(humidity) = find_row(RowLabel::Humidity) {
+            if let Ok(humidity) = collect_value_info(humidity.to_owned(), "%") {
+                measurement.humidity = Some(humidity);
+            }
+        }
+
+        if let Some(pressure) = find_row(RowLabel::Pressure) {
+            if let Ok(pressure) = collect_value_info(pressure.to_owned(), "hPa") {
+                measurement.pressure = Some(pressure);
+            }
+        }
+
+        if let Some(precipitation) = find_row(RowLabel::Precipitation) {
+            if let Ok(precipitation) = collect_value_info(precipitation.to_owned(), "mm") {
+                measurement.precipitation = Some(precipitation);
+            }
+        }
+
+        // Both the current wind and the max wind speed of the day are given in km/h.
+        if let Some(wind) = find_row(RowLabel::Wind) {
             match collect_wind_info(wind.to_owned(), true) {
                 Ok((speed, direction)) => {
                     measurement.wind_speed = speed;
@@ -234,9 +692,18 @@ fn consume_span(
         }
 
         // Meteoclimatic does not provide gusts speed. They only provide max wind speed
-        // of the day (counting perhaps from midnight UTC).
+        // of the day (counting perhaps from midnight UTC), which is reported separately.
         measurement.gusts_speed = None;
 
+        if let Some(daily_max_wind) = find_row(RowLabel::DailyMaxWind) {
+            if let Ok((speed, _)) = collect_wind_info(daily_max_wind.to_owned(), false) {
+                let since = last_update.date_naive().and_time(NaiveTime::MIN);
+                measurement.daily_max_wind = Some(speed);
+                measurement.daily_max_wind_since =
+                    Some(since.format("%Y-%m-%dT%H:%MZ").to_string());
+            }
+        }
+
         station.available = true;
         measurements.push(measurement);
     } else {
@@ -245,16 +712,67 @@ fn consume_span(
     Ok(())
 }
 
-fn parse_time_utc(time_utc_str: &str) -> Result<chrono::DateTime<Utc>, anyhow::Error> {
-    let utc_now = Utc::now();
+// Clocks of some stations are a few minutes ahead. Such timestamps are
+// taken as the current time rather than the same time of yesterday.
+const FUTURE_SKEW_TOLERANCE_MINUTES: i64 = 15;
+
+// Tooltips give the time only, as "HH:MM".
+fn parse_time_utc(
+    time_utc_str: &str,
+    utc_now: DateTime<Utc>,
+) -> Result<DateTime<Utc>, anyhow::Error> {
     let time_utc = NaiveTime::parse_from_str(time_utc_str, "%H:%M")
         .map_err(|e| anyhow::anyhow!("Invalid time format: {}", e))?;
-    let date_time_utc = utc_now.date_naive().and_time(time_utc);
-    let mut date_time = Utc::from_utc_datetime(&Utc, &date_time_utc);
-    if date_time > utc_now {
-        date_time -= Duration::days(1);
+    Ok(resolve_timestamp(time_utc, utc_now))
+}
+
+// The latest of yesterday, today and tomorrow is taken that isn't ahead of
+// the current time by more than the tolerance. Without a date, readings
+// older than a day can't be told apart from recent ones, they're taken as
+// of the last day.
+fn resolve_timestamp(time_utc: NaiveTime, utc_now: DateTime<Utc>) -> DateTime<Utc> {
+    let latest_allowed = utc_now + Duration::minutes(FUTURE_SKEW_TOLERANCE_MINUTES);
+    let today = utc_now.date_naive().and_time(time_utc).and_utc();
+    let date_time = [today + Duration::days(1), today, today - Duration::days(1)]
+        .into_iter()
+        .find(|date_time| *date_time <= latest_allowed)
+        .unwrap_or(today - Duration::days(1));
+    date_time.min(utc_now)
+}
+
+// Readings carry the time of day only, so the one of a station silent for
+// more than a day looks fresh. When the last recorded sample of the station
+// is older by whole days, the reading hasn't changed since and gets the time
+// of the sample, so that it's found stale. Returns number of such readings.
+pub fn date_back_silent_readings(
+    measurements: &mut [Measurement],
+    stations: &[Station],
+    history: &History,
+) -> usize {
+    let mut dated_back_count = 0;
+    for measurement in measurements.iter_mut() {
+        let is_meteoclimatic = stations.iter().any(|station| {
+            station.id == measurement.station_id && station.provider == "meteoclimatic"
+        });
+        if !is_meteoclimatic {
+            continue;
+        }
+        let Some(time) = parse_last_update(&measurement.last_update) else {
+            continue;
+        };
+        let Some(sample) = history.samples(&measurement.station_id).last() else {
+            continue;
+        };
+        let Some(sample_time) = sample.time() else {
+            continue;
+        };
+        let minutes = (time - sample_time).num_minutes();
+        if minutes > 0 && minutes % (24 * 60) == 0 {
+            measurement.last_update = sample.last_update.to_owned();
+            dated_back_count += 1;
+        }
     }
-    Ok(date_time)
+    dated_back_count
 }
 
 fn collect_station_info(span: ElementRef<'_>) -> anyhow::Result<(String, i64)> {
@@ -316,7 +834,7 @@ fn collect_wind_info(line: String, with_direction: bool) -> anyhow::Result<(u64,
     let mut direction: Option<f64> = None;
 
     if line != "Calma" {
-        let wind_parts = line.split_whitespace().collect::<Vec<&str>>();
+        let wind_parts = wind_parts(&line);
 
         if let Some(unit_parsed) = wind_parts.get(1) {
             if *unit_parsed != "km/h" {
@@ -343,12 +861,15 @@ fn collect_wind_info(line: String, with_direction: bool) -> anyhow::Result<(u64,
     Ok((speed, direction))
 }
 
+fn parse_decimal(val_str: &str) -> Option<f64> {
+    val_str.trim().replace(",", ".").parse::<f64>().ok()
+}
+
 fn collect_temp_info(line: String) -> anyhow::Result<f64> {
     let mut parts = line.split("°C");
     if parts.clone().count() == 3 {
         if let Some(val_str) = parts.next() {
-            let val_str = val_str.replace(",", ".");
-            if let Ok(temp) = val_str.parse::<f64>() {
+            if let Some(temp) = parse_decimal(val_str) {
                 return Ok(temp);
             } else {
                 anyhow::bail!("Invalid temperature value: {}", val_str);
@@ -357,3 +878,409 @@ fn collect_temp_info(line: String) -> anyhow::Result<f64> {
     }
     anyhow::bail!("Invalid temperature format");
 }
+
+// Reads the first value followed by the given unit, e.g. "1015,2 hPa".
+fn collect_value_info(line: String, unit: &str) -> anyhow::Result<f64> {
+    if let Some((val_str, _)) = line.split_once(unit) {
+        let val_str = val_str.split_whitespace().last().unwrap_or("");
+        if let Some(value) = parse_decimal(val_str) {
+            return Ok(value);
+        } else {
+            anyhow::bail!("Invalid value '{}' for unit '{}'", val_str, unit);
+        }
+    }
+    anyhow::bail!("Invalid format, unit '{}' expected: {}", unit, line);
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn row(caption: &str, value: &str) -> Row {
+        Row {
+            caption: caption.to_owned(),
+            value: value.to_owned(),
+        }
+    }
+
+    #[test]
+    fn label_row_by_caption() {
+        let cases = [
+            (row("Temperatura", "15,2 °C"), Some(RowLabel::Temperature)),
+            (row("Temperatura máxima", "18,1 °C"), None),
+            (row("Punto de rocío", "6,4 °C"), None),
+            (row("Humedad", "48 %"), Some(RowLabel::Humidity)),
+            (row("Presión", "1015,2 hPa"), Some(RowLabel::Pressure)),
+            (
+                row("Precipitación", "0,4 mm"),
+                Some(RowLabel::Precipitation),
+            ),
+            (row("Viento", "12 km/h NW"), Some(RowLabel::Wind)),
+            (row("Viento", "Calma"), Some(RowLabel::Wind)),
+            (
+                row("Racha máxima", "41 km/h NW"),
+                Some(RowLabel::DailyMaxWind),
+            ),
+            (row("Viento máx.", "25 km/h"), Some(RowLabel::DailyMaxWind)),
+            (row("Índice UV", "5"), None),
+            (
+                row("", "Actualizado: 10:05 UTC"),
+                Some(RowLabel::LastUpdate),
+            ),
+        ];
+        for (row, label) in cases {
+            assert_eq!(label_row(&row), label, "{:?}", row);
+        }
+    }
+
+    #[test]
+    fn label_row_by_unit_tells_current_wind_from_daily_max() {
+        let label = |value: &str| label_row(&row("", value));
+        assert_eq!(label("12 km/h NW"), Some(RowLabel::Wind));
+        assert_eq!(label("Calma"), Some(RowLabel::Wind));
+        assert_eq!(label("0 km/h"), Some(RowLabel::Wind));
+        assert_eq!(label("25 km/h"), Some(RowLabel::DailyMaxWind));
+        assert_eq!(label("Máx: 25 km/h"), Some(RowLabel::DailyMaxWind));
+        assert_eq!(label("Máx. 25 km/h NW"), Some(RowLabel::DailyMaxWind));
+        assert_eq!(label("65 % 1013,8 hPa"), Some(RowLabel::Pressure));
+    }
+
+    fn consume_tooltips() -> (HashMap<String, Station>, Vec<Measurement>) {
+        let document = Html::parse_document(include_str!(
+            "../tests/fixtures/meteoclimatic_tooltips.html"
+        ));
+        let mut stations: HashMap<String, Station> = ["ESCAT0800000008572A", "ESCAT0800000008870D"]
+            .into_iter()
+            .map(|vendor_id| (format!("/perfil/{}", vendor_id), station(vendor_id)))
+            .collect();
+        let mut measurements = vec![];
+        let tooltip_selector = parse_selector("span.tooltip").unwrap();
+        for span in document.select(&tooltip_selector) {
+            consume_span(
+                span,
+                &mut stations,
+                &mut measurements,
+                utc("2024-05-12T10:10:00Z"),
+            )
+            .unwrap();
+        }
+        (stations, measurements)
+    }
+
+    #[test]
+    fn consume_span_of_captioned_rows() {
+        let (stations, measurements) = consume_tooltips();
+        let station = &stations["/perfil/ESCAT0800000008572A"];
+        assert_eq!(station.name, "Bellmunt (Sant Pere de Torelló)");
+        assert_eq!(station.elevation, Some(1246));
+        assert!(station.available);
+
+        let measurement = &measurements[0];
+        assert_eq!(measurement.last_update, "2024-05-12T10:05Z");
+        // Not the dew point nor the max temperature of the day.
+        assert_eq!(measurement.temperature, Some(15.2));
+        assert_eq!(measurement.wind_speed, 12);
+        assert_eq!(measurement.wind_direction, Some(315.0));
+        assert_eq!(measurement.daily_max_wind, Some(41));
+        assert_eq!(
+            measurement.daily_max_wind_since.as_deref(),
+            Some("2024-05-12T00:00Z")
+        );
+        assert_eq!(measurement.humidity, Some(48.0));
+        assert_eq!(measurement.pressure, Some(1015.2));
+        assert_eq!(measurement.precipitation, Some(0.4));
+    }
+
+    #[test]
+    fn consume_span_of_rows_without_captions() {
+        let (_, measurements) = consume_tooltips();
+        let measurement = &measurements[1];
+        assert_eq!(measurement.last_update, "2024-05-12T09:55Z");
+        assert_eq!(measurement.temperature, Some(21.4));
+        assert_eq!(measurement.wind_speed, 0);
+        assert_eq!(measurement.wind_direction, None);
+        assert_eq!(measurement.daily_max_wind, Some(18));
+        // Row with both the humidity and the pressure is taken as the pressure.
+        assert_eq!(measurement.pressure, Some(1013.8));
+        assert_eq!(measurement.humidity, None);
+        assert_eq!(measurement.precipitation, Some(1.2));
+    }
+
+    #[test]
+    fn collect_value_info_of_units() {
+        assert_eq!(collect_value_info("48 %".to_owned(), "%").unwrap(), 48.0);
+        assert_eq!(
+            collect_value_info("1015,2 hPa".to_owned(), "hPa").unwrap(),
+            1015.2
+        );
+        assert_eq!(
+            collect_value_info("Presión: 1015.2 hPa".to_owned(), "hPa").unwrap(),
+            1015.2
+        );
+        // The first value followed by the unit.
+        assert_eq!(
+            collect_value_info("65 % 1013,8 hPa".to_owned(), "hPa").unwrap(),
+            1013.8
+        );
+        assert_eq!(
+            collect_value_info("65 % 1013,8 hPa".to_owned(), "%").unwrap(),
+            65.0
+        );
+    }
+
+    #[test]
+    fn collect_value_info_rejects_invalid_values() {
+        assert!(collect_value_info("1015,2 hPa".to_owned(), "mm").is_err());
+        assert!(collect_value_info("-- hPa".to_owned(), "hPa").is_err());
+        assert!(collect_value_info("hPa".to_owned(), "hPa").is_err());
+    }
+
+    fn station(vendor_id: &str) -> Station {
+        Station {
+            id: vendor_id.to_owned(),
+            name: vendor_id.to_owned(),
+            elevation: None,
+            url: String::new(),
+            lat: 0.0,
+            long: 0.0,
+            available: false,
+            provider: "meteoclimatic".to_owned(),
+            vendor_id: vendor_id.to_owned(),
+            alternates: vec![],
+        }
+    }
+
+    // Map where x grows by 100 px per degree of longitude and y by 100 px
+    // per degree of latitude southwards, which is close enough to Mercator
+    // around 41° N. Stations are put on the map at the given locations.
+    fn locate(
+        map: &[(&str, f64, f64)],
+        references: &[(&str, f64, f64)],
+        placement: &[ReferencePoint],
+    ) -> HashMap<String, Station> {
+        let mut stations = HashMap::new();
+        let mut pixels = HashMap::new();
+        for (vendor_id, lat, long) in map {
+            let href = format!("/perfil/{}", vendor_id);
+            stations.insert(href.clone(), station(vendor_id));
+            pixels.insert(href, ((long - 1.0) * 100.0, (42.0 - lat) * 100.0));
+        }
+        let references = references
+            .iter()
+            .map(|(vendor_id, lat, long)| (vendor_id.to_string(), (*lat, *long)))
+            .collect();
+        locate_stations(
+            &mut stations,
+            &pixels,
+            &references,
+            &HashMap::new(),
+            placement,
+        );
+        stations
+    }
+
+    const MAP: &[(&str, f64, f64)] = &[
+        ("ESCAT_A", 41.4, 1.4),
+        ("ESCAT_B", 41.6, 1.6),
+        ("ESCAT_C", 41.4, 1.6),
+        ("ESCAT_D", 41.6, 1.4),
+        ("ESCAT_E", 41.45, 1.55),
+        ("ESCAT_X", 41.5, 1.5),
+    ];
+
+    fn assert_located(stations: &HashMap<String, Station>, lat: f64, long: f64) {
+        let located = &stations["/perfil/ESCAT_X"];
+        assert!((located.lat - lat).abs() < 0.01, "{}", located.lat);
+        assert!((located.long - long).abs() < 0.01, "{}", located.long);
+    }
+
+    #[test]
+    fn locate_stations_with_enough_reference_points() {
+        let stations = locate(MAP, &MAP[..4], &[]);
+        assert_eq!(stations.len(), MAP.len());
+        assert_located(&stations, 41.5, 1.5);
+    }
+
+    #[test]
+    fn locate_stations_drops_inconsistent_reference_points() {
+        let mut references = MAP[..5].to_vec();
+        // Far from where its position on the map puts it.
+        references[3] = ("ESCAT_D", 41.9, 1.1);
+        let stations = locate(MAP, &references, &[]);
+        assert_located(&stations, 41.5, 1.5);
+    }
+
+    #[test]
+    fn locate_stations_with_few_reference_points() {
+        let stations = locate(MAP, &MAP[..2], &[]);
+        assert_eq!(stations.len(), MAP.len());
+        assert_located(&stations, 41.5, 1.5);
+    }
+
+    #[test]
+    fn locate_stations_keeps_placement_until_calibrated() {
+        // Pixel locations of A and B on the map, put 0.1° further east.
+        let placement = [
+            ReferencePoint {
+                x: 40.0,
+                y: 60.0,
+                lat: 41.4,
+                long: 1.5,
+            },
+            ReferencePoint {
+                x: 60.0,
+                y: 40.0,
+                lat: 41.6,
+                long: 1.7,
+            },
+        ];
+        let stations = locate(MAP, &MAP[..3], &placement);
+        assert_eq!(stations.len(), MAP.len());
+        assert_located(&stations, 41.5, 1.6);
+
+        // Calibration takes over once there are enough reference points.
+        let stations = locate(MAP, &MAP[..4], &placement);
+        assert_located(&stations, 41.5, 1.5);
+    }
+
+    #[test]
+    fn locate_stations_without_reference_points() {
+        let stations = locate(MAP, &MAP[..1], &[]);
+        assert!(stations.is_empty());
+    }
+
+    #[test]
+    fn escat_placement_of_reference_stations() {
+        let calibration = Calibration::fit(ESCAT_PLACEMENT).unwrap();
+        for (vendor_id, lat, long) in &ESCAT_REFERENCE_STATIONS[..2] {
+            let point = ESCAT_PLACEMENT
+                .iter()
+                .find(|point| point.lat == *lat && point.long == *long)
+                .unwrap_or_else(|| panic!("{} not placed", vendor_id));
+            assert!(calibration.error_m(point) < 1.0);
+        }
+    }
+
+    fn utc(s: &str) -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339(s).unwrap().with_timezone(&Utc)
+    }
+
+    fn time(s: &str) -> NaiveTime {
+        NaiveTime::parse_from_str(s, "%H:%M").unwrap()
+    }
+
+    #[test]
+    fn resolve_timestamp_before_midnight() {
+        let now = utc("2024-05-12T00:05:00Z");
+        assert_eq!(
+            resolve_timestamp(time("23:58"), now),
+            utc("2024-05-11T23:58:00Z")
+        );
+    }
+
+    #[test]
+    fn resolve_timestamp_of_clock_ahead_past_midnight() {
+        let now = utc("2024-05-11T23:55:00Z");
+        assert_eq!(resolve_timestamp(time("00:03"), now), now);
+    }
+
+    #[test]
+    fn resolve_timestamp_within_tolerance() {
+        let now = utc("2024-05-12T10:00:00Z");
+        assert_eq!(
+            resolve_timestamp(time("09:45"), now),
+            utc("2024-05-12T09:45:00Z")
+        );
+        assert_eq!(resolve_timestamp(time("10:15"), now), now);
+        assert_eq!(
+            resolve_timestamp(time("10:16"), now),
+            utc("2024-05-11T10:16:00Z")
+        );
+    }
+
+    fn reading(station_id: &str, last_update: &str) -> Measurement {
+        Measurement {
+            station_id: station_id.to_owned(),
+            last_update: last_update.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    // History with a sample of station "a" recorded a day before the readings.
+    fn silent_station_history(stations: &[Station]) -> History {
+        let mut history = History::default();
+        history.record(
+            &[reading("a", "2024-05-11T09:45Z")],
+            stations,
+            utc("2024-05-11T10:00:00Z"),
+        );
+        history
+    }
+
+    #[test]
+    fn date_back_silent_readings_of_same_time_of_day() {
+        let stations = [station("a"), station("b")];
+        let history = silent_station_history(&stations);
+        let mut measurements = [
+            reading("a", "2024-05-12T09:45Z"),
+            reading("b", "2024-05-12T09:45Z"),
+        ];
+        assert_eq!(
+            date_back_silent_readings(&mut measurements, &stations, &history),
+            1
+        );
+        assert_eq!(measurements[0].last_update, "2024-05-11T09:45Z");
+        // Nothing recorded of the station yet.
+        assert_eq!(measurements[1].last_update, "2024-05-12T09:45Z");
+    }
+
+    #[test]
+    fn date_back_silent_readings_keeps_new_readings() {
+        let stations = [station("a")];
+        let history = silent_station_history(&stations);
+        for last_update in [
+            "2024-05-11T09:45Z",
+            "2024-05-11T10:00Z",
+            "2024-05-12T09:50Z",
+        ] {
+            let mut measurements = [reading("a", last_update)];
+            assert_eq!(
+                date_back_silent_readings(&mut measurements, &stations, &history),
+                0
+            );
+            assert_eq!(measurements[0].last_update, last_update);
+        }
+    }
+
+    #[test]
+    fn date_back_silent_readings_of_other_providers() {
+        let mut stations = [station("a")];
+        let history = silent_station_history(&stations);
+        stations[0].provider = "meteocat".to_owned();
+        let mut measurements = [reading("a", "2024-05-12T09:45Z")];
+        assert_eq!(
+            date_back_silent_readings(&mut measurements, &stations, &history),
+            0
+        );
+    }
+
+    #[test]
+    fn parse_time_utc_requires_time_only() {
+        let now = utc("2024-05-12T10:00:00Z");
+        assert_eq!(
+            parse_time_utc("09:45", now).unwrap(),
+            utc("2024-05-12T09:45:00Z")
+        );
+        assert!(parse_time_utc("12/05/2024 09:45", now).is_err());
+    }
+
+    #[test]
+    fn collect_wind_info_skips_label() {
+        let (speed, direction) = collect_wind_info("Máx: 25 km/h".to_owned(), false).unwrap();
+        assert_eq!(speed, 25);
+        assert_eq!(direction, None);
+        let (speed, direction) = collect_wind_info("12 km/h NW".to_owned(), true).unwrap();
+        assert_eq!(speed, 12);
+        assert_eq!(direction, Some(315.0));
+    }
+}
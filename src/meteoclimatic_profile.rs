@@ -0,0 +1,237 @@
+use chrono::{DateTime, Duration, Utc};
+use encoding_rs::ISO_8859_15;
+use scraper::Html;
+use serde::{Deserialize, Serialize};
+use spin_sdk::http::{Method, Request, Response};
+use spin_sdk::key_value::Store;
+use std::collections::HashMap;
+
+const STORE_KEY: &str = "meteoclimatic:profiles";
+
+// Profile pages are fetched slowly, only a few per request, to be gentle
+// with Meteoclimatic. Eventually all the stations are covered.
+const PROFILES_PER_REQUEST: usize = 2;
+
+const REFRESH_AFTER_DAYS: i64 = 30;
+const RETRY_AFTER_DAYS: i64 = 1;
+
+#[derive(Debug, Clone, Copy, Serialize, Deserialize)]
+pub struct Profile {
+    pub lat: f64,
+    pub long: f64,
+    pub elevation: i64,
+}
+
+#[derive(Debug, Serialize, Deserialize)]
+struct CachedProfile {
+    // None if the profile page couldn't be parsed.
+    profile: Option<Profile>,
+    checked_at: String,
+}
+
+type CachedProfiles = HashMap<String, CachedProfile>;
+
+pub struct ProfileCache {
+    store: Store,
+    cached: CachedProfiles,
+}
+
+impl ProfileCache {
+    pub fn open() -> anyhow::Result<ProfileCache> {
+        let store = Store::open_default()?;
+        let cached = store
+            .get_json::<CachedProfiles>(STORE_KEY)?
+            .unwrap_or_default();
+        Ok(ProfileCache { store, cached })
+    }
+
+    // Profiles known so far, keyed by vendor ID.
+    pub fn profiles(&self) -> HashMap<String, Profile> {
+        self.cached
+            .iter()
+            .filter_map(|(vendor_id, cached)| cached.profile.map(|p| (vendor_id.to_owned(), p)))
+            .collect()
+    }
+
+    // Fetches profile pages of the stations that are not cached yet or that
+    // are due for a refresh, up to the limit per request.
+    pub async fn enrich(&mut self, vendor_ids: &[String]) -> anyhow::Result<()> {
+        let now = Utc::now();
+        let mut due = vendor_ids
+            .iter()
+            .filter(|vendor_id| match self.cached.get(*vendor_id) {
+                Some(cached) => {
+                    let after = if cached.profile.is_some() {
+                        Duration::days(REFRESH_AFTER_DAYS)
+                    } else {
+                        Duration::days(RETRY_AFTER_DAYS)
+                    };
+                    DateTime::parse_from_rfc3339(&cached.checked_at)
+                        .map(|checked_at| checked_at + after < now)
+                        .unwrap_or(true)
+                }
+                None => true,
+            })
+            .collect::<Vec<&String>>();
+        due.sort();
+
+        if due.is_empty() {
+            return Ok(());
+        }
+
+        for vendor_id in due.into_iter().take(PROFILES_PER_REQUEST) {
+            let profile = match fetch_profile(vendor_id).await {
+                Ok(profile) => Some(profile),
+                Err(e) => {
+                    println!("[{}] Profile unavailable: {}", vendor_id, e);
+                    None
+                }
+            };
+            self.cached.insert(
+                vendor_id.to_owned(),
+                CachedProfile {
+                    profile,
+                    checked_at: now.to_rfc3339(),
+                },
+            );
+        }
+
+        self.store.set_json(STORE_KEY, &self.cached)
+    }
+}
+
+fn profile_url(vendor_id: &str) -> String {
+    format!("https://www.meteoclimatic.net/perfil/{}", vendor_id)
+}
+
+async fn fetch_profile(vendor_id: &str) -> anyhow::Result<Profile> {
+    println!("[{}] Fetching profile...", vendor_id);
+    let request = Request::builder()
+        .method(Method::Get)
+        .uri(profile_url(vendor_id))
+        .build();
+
+    let response: Response = spin_sdk::http::send(request).await?;
+    if *response.status() != 200 {
+        anyhow::bail!("Unexpected status {}", response.status());
+    }
+    let (body, _, _) = ISO_8859_15.decode(response.body());
+    parse_profile(&body)
+}
+
+fn parse_profile(body: &str) -> anyhow::Result<Profile> {
+    let document = Html::parse_document(body);
+    let text = document
+        .root_element()
+        .text()
+        .collect::<Vec<&str>>()
+        .join("\n");
+
+    let lat = value_after(&text, "Latitud")
+        .and_then(parse_coordinate)
+        .ok_or_else(|| anyhow::anyhow!("Latitude not found"))?;
+    let long = value_after(&text, "Longitud")
+        .and_then(parse_coordinate)
+        .ok_or_else(|| anyhow::anyhow!("Longitude not found"))?;
+    let elevation = value_after(&text, "Altitud")
+        .and_then(|s| s.split_whitespace().next())
+        .and_then(|s| s.replace(['.', ','], "").parse::<i64>().ok())
+        .ok_or_else(|| anyhow::anyhow!("Altitude not found"))?;
+
+    Ok(Profile {
+        lat,
+        long,
+        elevation,
+    })
+}
+
+// Returns the text that follows the label, e.g. "41° 27' 34'' N" for "Latitud: 41° 27' 34'' N".
+fn value_after<'a>(text: &'a str, label: &str) -> Option<&'a str> {
+    let (_, rest) = text.split_once(label)?;
+    let rest = rest.trim_start_matches(|c: char| c == ':' || c.is_whitespace());
+    let value = rest.lines().next()?.trim();
+    (!value.is_empty()).then_some(value)
+}
+
+// Supports decimal degrees ("41.4596", "41,4596") and degrees, minutes
+// and seconds ("41° 27' 34'' N", "2º14'36\"E"). Remarks in parentheses,
+// e.g. "(WGS84)", are ignored.
+fn parse_coordinate(value: &str) -> Option<f64> {
+    let value = value.split('(').next()?.trim();
+    // Hemisphere is given by the trailing letter, "O" stands for "Oeste".
+    let (value, hemisphere) = match value.char_indices().last() {
+        Some((index, c)) if matches!(c, 'N' | 'S' | 'E' | 'W' | 'O') => {
+            (value[..index].trim_end(), Some(c))
+        }
+        _ => (value, None),
+    };
+    let negative = matches!(hemisphere, Some('S' | 'W' | 'O')) || value.starts_with('-');
+    let numbers = value
+        .split(|c: char| !(c.is_ascii_digit() || c == '.' || c == ','))
+        .filter(|s| !s.is_empty())
+        .map(|s| s.replace(',', ".").parse::<f64>())
+        .collect::<Result<Vec<f64>, _>>()
+        .ok()?;
+
+    let degrees = match numbers.as_slice() {
+        [d] => *d,
+        [d, m] => d + m / 60.0,
+        [d, m, s, ..] => d + m / 60.0 + s / 3600.0,
+        [] => return None,
+    };
+    Some(if negative { -degrees } else { degrees })
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("coordinate expected");
+        assert!(
+            (actual - expected).abs() < 1e-4,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn parse_coordinate_reads_decimal_degrees() {
+        assert_close(parse_coordinate("41.4596"), 41.4596);
+        assert_close(parse_coordinate("41,4596"), 41.4596);
+        assert_close(parse_coordinate("-0.8765"), -0.8765);
+    }
+
+    #[test]
+    fn parse_coordinate_ignores_remarks() {
+        assert_close(parse_coordinate("41,4596 (WGS84)"), 41.4596);
+        assert_close(parse_coordinate("2,1234 E (WGS84)"), 2.1234);
+    }
+
+    #[test]
+    fn parse_coordinate_reads_degrees_minutes_seconds() {
+        assert_close(
+            parse_coordinate("41° 27' 34'' N"),
+            41.0 + 27.0 / 60.0 + 34.0 / 3600.0,
+        );
+        assert_close(
+            parse_coordinate("2º14'36\"E"),
+            2.0 + 14.0 / 60.0 + 36.0 / 3600.0,
+        );
+        assert_close(
+            parse_coordinate("0° 52' 12'' O"),
+            -(52.0 / 60.0 + 12.0 / 3600.0),
+        );
+        assert_close(parse_coordinate("3° 30' W"), -3.5);
+    }
+
+    #[test]
+    fn parse_profile_reads_saved_page() {
+        let body = include_str!("../tests/fixtures/meteoclimatic_profile.html");
+        let profile = parse_profile(body).unwrap();
+        assert_close(Some(profile.lat), 42.0 + 6.0 / 60.0 + 6.0 / 3600.0);
+        assert_close(Some(profile.long), 2.0 + 17.0 / 60.0 + 40.0 / 3600.0);
+        assert_eq!(profile.elevation, 1246);
+    }
+}
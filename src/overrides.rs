@@ -0,0 +1,186 @@
+use crate::common::{legacy_station_id_of, Measurement, Station};
+
+use serde::Deserialize;
+use spin_sdk::key_value::Store;
+use std::collections::{HashMap, HashSet};
+
+const STORE_KEY: &str = "stations:overrides";
+
+// Correction of a station reported by a data source. Overrides are kept
+// in the default key-value store, keyed by ID of the station or by
+// "provider:vendor_id", e.g.
+//
+// {
+//   "meteocat:X4": { "name": "Barcelona - Raval" },
+//   "9648493fa0e6957dbe03eac2b18d1589": { "exclude": true }
+// }
+#[derive(Debug, Deserialize)]
+struct StationOverride {
+    #[serde(default)]
+    exclude: bool,
+    name: Option<String>,
+    lat: Option<f64>,
+    long: Option<f64>,
+    elevation: Option<i64>,
+}
+
+#[derive(Default)]
+pub struct Overrides(HashMap<String, StationOverride>);
+
+pub fn load() -> anyhow::Result<Overrides> {
+    let store = Store::open_default()?;
+    let overrides = store
+        .get_json::<HashMap<String, StationOverride>>(STORE_KEY)?
+        .unwrap_or_default();
+    Ok(Overrides(overrides))
+}
+
+impl Overrides {
+    fn find(&self, station: &Station) -> Option<&StationOverride> {
+        self.0
+            .get(&station.id)
+            .or_else(|| self.0.get(&legacy_station_id_of(&station.url)))
+            .or_else(|| {
+                self.0
+                    .get(&format!("{}:{}", station.provider, station.vendor_id))
+            })
+    }
+
+    // Drops the excluded stations with their measurements and corrects the remaining ones.
+    pub fn apply(
+        &self,
+        stations: Vec<Station>,
+        measurements: Vec<Measurement>,
+    ) -> (Vec<Station>, Vec<Measurement>) {
+        if self.0.is_empty() {
+            return (stations, measurements);
+        }
+
+        let mut excluded_ids = HashSet::new();
+        let stations = stations
+            .into_iter()
+            .filter_map(|mut station| {
+                let Some(station_override) = self.find(&station) else {
+                    return Some(station);
+                };
+                if station_override.exclude {
+                    excluded_ids.insert(station.id);
+                    return None;
+                }
+                if let Some(name) = &station_override.name {
+                    station.name = name.to_owned();
+                }
+                if let Some(lat) = station_override.lat {
+                    station.lat = lat;
+                }
+                if let Some(long) = station_override.long {
+                    station.long = long;
+                }
+                if let Some(elevation) = station_override.elevation {
+                    station.elevation = Some(elevation);
+                }
+                Some(station)
+            })
+            .collect();
+
+        let measurements = measurements
+            .into_iter()
+            .filter(|measurement| !excluded_ids.contains(&measurement.station_id))
+            .collect();
+
+        (stations, measurements)
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::common::station_id_of;
+
+    fn overrides(json: &str) -> Overrides {
+        Overrides(serde_json::from_str(json).unwrap())
+    }
+
+    fn station(vendor_id: &str) -> Station {
+        Station {
+            id: station_id_of("meteocat", vendor_id),
+            name: vendor_id.to_owned(),
+            elevation: None,
+            url: format!("https://www.meteo.cat/estacions/{}", vendor_id),
+            lat: 41.4,
+            long: 2.2,
+            available: true,
+            provider: "meteocat".to_owned(),
+            vendor_id: vendor_id.to_owned(),
+            alternates: vec![],
+        }
+    }
+
+    fn measurement(station: &Station) -> Measurement {
+        Measurement {
+            station_id: station.id.to_owned(),
+            ..Default::default()
+        }
+    }
+
+    fn apply(overrides: &Overrides, vendor_ids: &[&str]) -> (Vec<Station>, Vec<Measurement>) {
+        let stations: Vec<Station> = vendor_ids.iter().map(|id| station(id)).collect();
+        let measurements = stations.iter().map(measurement).collect();
+        overrides.apply(stations, measurements)
+    }
+
+    #[test]
+    fn apply_corrects_station() {
+        let overrides = overrides(
+            r#"{"meteocat:X4": {"name": "Barcelona - Raval", "lat": 41.3839,
+                "long": 2.1679, "elevation": 33}}"#,
+        );
+        let (stations, measurements) = apply(&overrides, &["X4", "D5"]);
+        assert_eq!(stations[0].name, "Barcelona - Raval");
+        assert_eq!((stations[0].lat, stations[0].long), (41.3839, 2.1679));
+        assert_eq!(stations[0].elevation, Some(33));
+        // Station ID and the other stations aren't changed.
+        assert_eq!(stations[0].id, station_id_of("meteocat", "X4"));
+        assert_eq!(stations[1].name, "D5");
+        assert_eq!(measurements.len(), 2);
+    }
+
+    #[test]
+    fn apply_keeps_values_not_overridden() {
+        let overrides = overrides(r#"{"meteocat:X4": {"elevation": 33}}"#);
+        let (stations, _) = apply(&overrides, &["X4"]);
+        assert_eq!(stations[0].name, "X4");
+        assert_eq!((stations[0].lat, stations[0].long), (41.4, 2.2));
+        assert_eq!(stations[0].elevation, Some(33));
+    }
+
+    #[test]
+    fn apply_excludes_station_with_measurements() {
+        let x4_id = station_id_of("meteocat", "X4");
+        let overrides = overrides(&format!(r#"{{"{}": {{"exclude": true}}}}"#, x4_id));
+        let (stations, measurements) = apply(&overrides, &["X4", "D5"]);
+        assert_eq!(stations.len(), 1);
+        assert_eq!(stations[0].vendor_id, "D5");
+        assert_eq!(measurements.len(), 1);
+        assert_eq!(measurements[0].station_id, stations[0].id);
+    }
+
+    #[test]
+    fn find_by_id_legacy_id_or_vendor_id() {
+        let x4 = station("X4");
+        let legacy_id = legacy_station_id_of(&x4.url);
+        let keys = [x4.id.to_owned(), legacy_id, "meteocat:X4".to_owned()];
+        for key in keys {
+            let overrides = overrides(&format!(r#"{{"{}": {{"name": "X"}}}}"#, key));
+            assert_eq!(
+                overrides.find(&x4).and_then(|o| o.name.as_deref()),
+                Some("X"),
+                "{}",
+                key
+            );
+        }
+        // Vendor ID of another provider.
+        let overrides = overrides(r#"{"aemet:X4": {"name": "X"}}"#);
+        assert!(overrides.find(&x4).is_none());
+    }
+}
@@ -0,0 +1,191 @@
+use crate::common::{fetch_utf8, station_id_of, BoundingBox, Measurement, Station};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+const LIVE_URL: &str = "https://api.pioupiou.fr/v1/live-with-meta/all";
+const STATION_BASE_URL: &str = "https://www.openwindmap.org";
+
+#[derive(Debug, Deserialize)]
+struct MetaRaw {
+    name: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct LocationRaw {
+    latitude: Option<f64>,
+    longitude: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct MeasurementsRaw {
+    date: Option<String>,
+    wind_heading: Option<f64>,
+    wind_speed_avg: Option<f64>,
+    wind_speed_max: Option<f64>,
+    pressure: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StatusRaw {
+    state: Option<String>,
+}
+
+#[derive(Debug, Deserialize)]
+struct SensorRaw {
+    id: u64,
+    meta: MetaRaw,
+    location: LocationRaw,
+    measurements: MeasurementsRaw,
+    status: StatusRaw,
+}
+
+#[derive(Debug, Deserialize)]
+struct LiveRaw {
+    data: Vec<SensorRaw>,
+}
+
+// Sensors are worldwide, only those within the area are taken.
+pub fn bbox() -> anyhow::Result<Option<BoundingBox>> {
+    let bbox = spin_sdk::variables::get("pioupiou_bbox")?;
+    if bbox.trim().is_empty() {
+        return Ok(None);
+    }
+    Ok(Some(BoundingBox::parse(&bbox)?))
+}
+
+pub async fn fetch_data(bbox: &BoundingBox) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+    println!("[pioupiou] Fetching...");
+    let body = fetch_utf8(LIVE_URL).await?;
+
+    println!("Analyzing...");
+    let live_raw: LiveRaw = serde_json::from_str(&body)?;
+    Ok(convert(live_raw, bbox))
+}
+
+fn convert(live_raw: LiveRaw, bbox: &BoundingBox) -> (Vec<Station>, Vec<Measurement>) {
+    let mut available_stations: Vec<Station> = vec![];
+    let mut measurements: Vec<Measurement> = vec![];
+
+    for sensor in live_raw.data {
+        let (Some(lat), Some(long)) = (sensor.location.latitude, sensor.location.longitude) else {
+            continue;
+        };
+        if !bbox.contains(lat, long) || sensor.status.state.as_deref() != Some("on") {
+            continue;
+        }
+
+        let vendor_id = sensor.id;
+        let Some(wind_speed) = sensor.measurements.wind_speed_avg.map(|v| v.round() as u64) else {
+            println!("[{}] Wind information not available", vendor_id);
+            continue;
+        };
+        let last_update = match sensor
+            .measurements
+            .date
+            .as_deref()
+            .map(DateTime::parse_from_rfc3339)
+        {
+            Some(Ok(date)) => date
+                .with_timezone(&Utc)
+                .format("%Y-%m-%dT%H:%MZ")
+                .to_string(),
+            _ => {
+                println!(
+                    "[{}] Invalid time {:?}",
+                    vendor_id, sensor.measurements.date
+                );
+                continue;
+            }
+        };
+
+        let station_url = format!("{}/pioupiou-{}", STATION_BASE_URL, vendor_id);
+        let station_id = station_id_of("pioupiou", &vendor_id.to_string());
+
+        let measurement = Measurement {
+            station_id: station_id.clone(),
+            wind_speed,
+            wind_direction: sensor.measurements.wind_heading.filter(|_| wind_speed != 0),
+            gusts_speed: sensor.measurements.wind_speed_max.map(|v| v.round() as u64),
+            pressure: sensor.measurements.pressure,
+            last_update,
+            ..Default::default()
+        };
+        let station = Station {
+            id: station_id,
+            name: sensor
+                .meta
+                .name
+                .unwrap_or_else(|| format!("Pioupiou {}", vendor_id)),
+            // Pioupiou doesn't report elevation.
+            elevation: None,
+            url: station_url,
+            lat,
+            long,
+            available: true,
+            provider: "pioupiou".to_owned(),
+            vendor_id: vendor_id.to_string(),
+            alternates: vec![],
+        };
+        available_stations.push(station);
+        measurements.push(measurement);
+    }
+
+    println!("Found {} available stations", available_stations.len());
+    (available_stations, measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    // Catalonia.
+    const BBOX: BoundingBox = BoundingBox {
+        min_lat: 40.5,
+        min_long: 0.1,
+        max_lat: 42.9,
+        max_long: 3.4,
+    };
+
+    fn live_raw() -> LiveRaw {
+        serde_json::from_str(include_str!("../tests/fixtures/pioupiou_live.json")).unwrap()
+    }
+
+    #[test]
+    fn convert_of_sensors_within_area() {
+        let (stations, measurements) = convert(live_raw(), &BBOX);
+
+        // Sensors out of the area, off, without location or without wind are skipped.
+        let vendor_ids: Vec<&str> = stations.iter().map(|s| s.vendor_id.as_str()).collect();
+        assert_eq!(vendor_ids, ["1125", "1131"]);
+        assert_eq!(measurements.len(), 2);
+
+        let station = &stations[0];
+        assert_eq!(station.id, station_id_of("pioupiou", "1125"));
+        assert_eq!(station.name, "Montsec - Àger");
+        assert_eq!(station.url, "https://www.openwindmap.org/pioupiou-1125");
+        assert_eq!(station.elevation, None);
+        assert_eq!((station.lat, station.long), (42.0412, 0.7501));
+
+        let measurement = &measurements[0];
+        assert_eq!(measurement.station_id, station.id);
+        assert_eq!(measurement.last_update, "2024-05-12T10:13Z");
+        assert_eq!(measurement.wind_speed, 18);
+        assert_eq!(measurement.gusts_speed, Some(28));
+        assert_eq!(measurement.wind_direction, Some(202.5));
+        assert_eq!(measurement.pressure, None);
+
+        // Unnamed sensor, and no direction of calm wind.
+        assert_eq!(stations[1].name, "Pioupiou 1131");
+        assert_eq!(measurements[1].wind_speed, 0);
+        assert_eq!(measurements[1].wind_direction, None);
+    }
+
+    #[test]
+    fn convert_of_other_area() {
+        let bbox = BoundingBox::parse("44.5,5.0,46.0,7.0").unwrap();
+        let (stations, _) = convert(live_raw(), &bbox);
+        assert_eq!(stations.len(), 1);
+        assert_eq!(stations[0].vendor_id, "110");
+    }
+}
@@ -0,0 +1,425 @@
+use crate::common::{distance_m, parse_last_update, Measurement, QcFlag, Station};
+use crate::history::History;
+
+use chrono::Duration;
+use std::collections::HashMap;
+
+// Plausible values in the units of the service.
+const RANGES: &[(&str, f64, f64)] = &[
+    ("wind_speed", 0.0, 200.0),
+    ("wind_direction", 0.0, 360.0),
+    ("gusts_speed", 0.0, 300.0),
+    ("gusts_direction", 0.0, 360.0),
+    ("daily_max_wind", 0.0, 300.0),
+    ("temperature", -35.0, 50.0),
+    ("humidity", 0.0, 100.0),
+    // Pressure at the station rather than at sea level is reported by some providers.
+    ("pressure", 500.0, 1100.0),
+    ("precipitation", 0.0, 500.0),
+    ("irradiance", 0.0, 1500.0),
+];
+
+// Largest plausible change since the previous measurement, if not older than STEP_WINDOW_MINUTES.
+const MAX_STEPS: &[(&str, f64)] = &[
+    ("wind_speed", 60.0),
+    ("temperature", 8.0),
+    ("pressure", 6.0),
+];
+const STEP_WINDOW_MINUTES: i64 = 60;
+
+// Neighbours are compared only if their measurements are this close in time.
+const SPATIAL_WINDOW_MINUTES: i64 = 60;
+const MIN_NEIGHBOURS: usize = 3;
+// Temperature decreases with elevation, by 6.5 °C per km on average.
+const LAPSE_RATE_PER_M: f64 = 0.0065;
+const MAX_TEMPERATURE_DEVIATION: f64 = 10.0;
+// Wind is local, only speeds far above the ones of all the neighbours are suspicious.
+const MAX_WIND_SPEED_RATIO: f64 = 3.0;
+const MAX_WIND_SPEED_EXCESS: f64 = 40.0;
+
+pub struct QcConfig {
+    pub spatial_radius_m: f64,
+}
+
+pub fn config() -> anyhow::Result<QcConfig> {
+    let spatial_radius_m = spin_sdk::variables::get("qc_spatial_radius_m")?
+        .trim()
+        .parse()?;
+    Ok(QcConfig { spatial_radius_m })
+}
+
+fn value_of(measurement: &Measurement, field: &str) -> Option<f64> {
+    match field {
+        "wind_speed" => Some(measurement.wind_speed as f64),
+        "wind_direction" => measurement.wind_direction,
+        "gusts_speed" => measurement.gusts_speed.map(|v| v as f64),
+        "gusts_direction" => measurement.gusts_direction,
+        "daily_max_wind" => measurement.daily_max_wind.map(|v| v as f64),
+        "temperature" => measurement.temperature,
+        "humidity" => measurement.humidity,
+        "pressure" => measurement.pressure,
+        "precipitation" => measurement.precipitation,
+        "irradiance" => measurement.irradiance,
+        _ => None,
+    }
+}
+
+// Value of a field that isn't flagged yet.
+fn clean_value_of(measurement: &Measurement, field: &str) -> Option<f64> {
+    value_of(measurement, field).filter(|_| !measurement.qc_flags.contains_key(field))
+}
+
+// Only the first reason is kept for every field.
+fn flag(measurement: &mut Measurement, field: &'static str, qc_flag: QcFlag) {
+    measurement.qc_flags.entry(field).or_insert(qc_flag);
+}
+
+// Flags implausible values of the measurements. Values are kept as they are.
+pub fn check(
+    measurements: &mut [Measurement],
+    stations: &[Station],
+    history: &History,
+    config: &QcConfig,
+) {
+    for measurement in measurements.iter_mut() {
+        check_ranges(measurement);
+        check_gusts(measurement);
+    }
+    check_neighbours(measurements, stations, config);
+    for measurement in measurements.iter_mut() {
+        check_steps(measurement, history);
+    }
+
+    let flagged_count = measurements
+        .iter()
+        .filter(|measurement| !measurement.qc_flags.is_empty())
+        .count();
+    if flagged_count > 0 {
+        println!("Flagged {} measurements", flagged_count);
+    }
+}
+
+fn check_ranges(measurement: &mut Measurement) {
+    for (field, min, max) in RANGES {
+        if let Some(value) = value_of(measurement, field) {
+            if !(*min..=*max).contains(&value) {
+                flag(measurement, field, QcFlag::OutOfRange);
+            }
+        }
+    }
+}
+
+fn check_gusts(measurement: &mut Measurement) {
+    if let Some(gusts_speed) = measurement.gusts_speed {
+        if gusts_speed < measurement.wind_speed {
+            flag(measurement, "gusts_speed", QcFlag::GustBelowWind);
+        }
+    }
+}
+
+fn median(values: &mut [f64]) -> f64 {
+    values.sort_by(|a, b| a.total_cmp(b));
+    let middle = values.len() / 2;
+    if values.len() % 2 == 0 {
+        (values[middle - 1] + values[middle]) / 2.0
+    } else {
+        values[middle]
+    }
+}
+
+fn check_neighbours(measurements: &mut [Measurement], stations: &[Station], config: &QcConfig) {
+    let stations_by_id: HashMap<&String, &Station> = stations
+        .iter()
+        .map(|station| (&station.id, station))
+        .collect();
+    let located: Vec<(usize, &Station, chrono::DateTime<chrono::Utc>)> = measurements
+        .iter()
+        .enumerate()
+        .filter_map(|(index, measurement)| {
+            let station = stations_by_id.get(&measurement.station_id)?;
+            let time = parse_last_update(&measurement.last_update)?;
+            Some((index, *station, time))
+        })
+        .collect();
+
+    let mut flags: Vec<(usize, &'static str)> = vec![];
+    for (index, station, time) in &located {
+        let neighbours: Vec<(usize, &Station)> = located
+            .iter()
+            .filter(|(other_index, other_station, other_time)| {
+                other_index != index
+                    && (*other_time - *time).abs() <= Duration::minutes(SPATIAL_WINDOW_MINUTES)
+                    && distance_m(
+                        station.lat,
+                        station.long,
+                        other_station.lat,
+                        other_station.long,
+                    ) <= config.spatial_radius_m
+            })
+            .map(|(other_index, other_station, _)| (*other_index, *other_station))
+            .collect();
+
+        let measurement = &measurements[*index];
+        if let Some(wind_speed) = clean_value_of(measurement, "wind_speed") {
+            let mut speeds: Vec<f64> = neighbours
+                .iter()
+                .filter_map(|(other_index, _)| {
+                    clean_value_of(&measurements[*other_index], "wind_speed")
+                })
+                .collect();
+            if speeds.len() >= MIN_NEIGHBOURS
+                && wind_speed > MAX_WIND_SPEED_RATIO * median(&mut speeds) + MAX_WIND_SPEED_EXCESS
+            {
+                flags.push((*index, "wind_speed"));
+            }
+        }
+
+        // Temperatures are compared as if all the stations were at sea level.
+        // Stations of unknown elevation are left out.
+        let temperature = clean_value_of(measurement, "temperature")
+            .zip(station.elevation)
+            .map(|(t, elevation)| t + LAPSE_RATE_PER_M * elevation as f64);
+        if let Some(temperature) = temperature {
+            let mut temperatures: Vec<f64> = neighbours
+                .iter()
+                .filter_map(|(other_index, other_station)| {
+                    let elevation = other_station.elevation?;
+                    clean_value_of(&measurements[*other_index], "temperature")
+                        .map(|t| t + LAPSE_RATE_PER_M * elevation as f64)
+                })
+                .collect();
+            if temperatures.len() >= MIN_NEIGHBOURS
+                && (temperature - median(&mut temperatures)).abs() > MAX_TEMPERATURE_DEVIATION
+            {
+                flags.push((*index, "temperature"));
+            }
+        }
+    }
+
+    for (index, field) in flags {
+        flag(&mut measurements[index], field, QcFlag::SpatialOutlier);
+    }
+}
+
+fn check_steps(measurement: &mut Measurement, history: &History) {
+    let Some(time) = parse_last_update(&measurement.last_update) else {
+        return;
+    };
+    let previous = history
+        .samples(&measurement.station_id)
+        .iter()
+        .rev()
+        .filter_map(|sample| Some((sample.time()?, sample)))
+        .find(|(previous_time, _)| *previous_time < time)
+        .filter(|(previous_time, _)| {
+            time - *previous_time <= Duration::minutes(STEP_WINDOW_MINUTES)
+        })
+        .map(|(_, sample)| sample.clone());
+    let Some(previous) = previous else {
+        return;
+    };
+
+    for (field, max_step) in MAX_STEPS {
+        let previous_value = match *field {
+            "wind_speed" => previous.wind_speed.map(|v| v as f64),
+            "temperature" => previous.temperature,
+            "pressure" => previous.pressure,
+            _ => None,
+        };
+        if let (Some(value), Some(previous_value)) =
+            (clean_value_of(measurement, field), previous_value)
+        {
+            if (value - previous_value).abs() > *max_step {
+                flag(measurement, field, QcFlag::StepChange);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use chrono::{DateTime, Utc};
+
+    const CONFIG: QcConfig = QcConfig {
+        spatial_radius_m: 20_000.0,
+    };
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-12T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn last_update(minutes_ago: i64) -> String {
+        (now() - Duration::minutes(minutes_ago))
+            .format("%Y-%m-%dT%H:%MZ")
+            .to_string()
+    }
+
+    // Stations about 1 km apart from each other.
+    fn station(id: &str, index: usize, elevation: Option<i64>) -> Station {
+        Station {
+            id: id.to_owned(),
+            name: id.to_uppercase(),
+            elevation,
+            url: String::new(),
+            lat: 41.5 + 0.01 * index as f64,
+            long: 2.0,
+            available: true,
+            provider: "meteocat".to_owned(),
+            vendor_id: id.to_owned(),
+            alternates: vec![],
+        }
+    }
+
+    fn measurement(station_id: &str, wind_speed: u64, temperature: f64) -> Measurement {
+        Measurement {
+            station_id: station_id.to_owned(),
+            wind_speed,
+            temperature: Some(temperature),
+            last_update: last_update(0),
+            ..Default::default()
+        }
+    }
+
+    fn flags(measurement: &Measurement) -> Vec<(&'static str, QcFlag)> {
+        measurement
+            .qc_flags
+            .iter()
+            .map(|(field, flag)| (*field, *flag))
+            .collect()
+    }
+
+    #[test]
+    fn check_ranges_flags_implausible_values() {
+        let mut measurement = measurement("a", 10, 60.0);
+        measurement.humidity = Some(101.0);
+        measurement.wind_direction = Some(360.0);
+        measurement.pressure = Some(450.0);
+        check_ranges(&mut measurement);
+        assert_eq!(
+            flags(&measurement),
+            [
+                ("humidity", QcFlag::OutOfRange),
+                ("pressure", QcFlag::OutOfRange),
+                ("temperature", QcFlag::OutOfRange),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_gusts_flags_gusts_below_wind() {
+        let mut measurement = measurement("a", 20, 15.0);
+        measurement.gusts_speed = Some(20);
+        check_gusts(&mut measurement);
+        assert!(measurement.qc_flags.is_empty());
+        measurement.gusts_speed = Some(19);
+        check_gusts(&mut measurement);
+        assert_eq!(
+            flags(&measurement),
+            [("gusts_speed", QcFlag::GustBelowWind)]
+        );
+    }
+
+    #[test]
+    fn check_neighbours_flags_spatial_outliers() {
+        let stations = [
+            station("a", 0, Some(100)),
+            station("b", 1, Some(100)),
+            station("c", 2, Some(100)),
+            // 1000 m higher, it's 6.5 °C colder there.
+            station("d", 3, Some(1100)),
+            station("e", 4, Some(100)),
+        ];
+        let mut measurements = vec![
+            measurement("a", 10, 15.0),
+            measurement("b", 12, 16.0),
+            measurement("c", 8, 14.0),
+            measurement("d", 11, 8.5),
+            // Above 3 × 10.5 + 40 km/h, 10.5 km/h being the median of the neighbours.
+            measurement("e", 75, 30.0),
+        ];
+        check_neighbours(&mut measurements, &stations, &CONFIG);
+        for measurement in &measurements[..4] {
+            assert!(measurement.qc_flags.is_empty(), "{:?}", measurement);
+        }
+        assert_eq!(
+            flags(&measurements[4]),
+            [
+                ("temperature", QcFlag::SpatialOutlier),
+                ("wind_speed", QcFlag::SpatialOutlier),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_neighbours_requires_enough_neighbours() {
+        let stations = [
+            station("a", 0, Some(100)),
+            station("b", 1, Some(100)),
+            // Unknown elevation, its temperature isn't compared.
+            station("c", 2, None),
+            station("e", 4, Some(100)),
+        ];
+        let mut measurements = vec![
+            measurement("a", 10, 15.0),
+            measurement("b", 12, 16.0),
+            measurement("c", 8, 14.0),
+            measurement("e", 40, 30.0),
+        ];
+        // Measurement of a neighbour too old to be compared.
+        measurements[2].last_update = last_update(90);
+        check_neighbours(&mut measurements, &stations, &CONFIG);
+        assert!(measurements.iter().all(|m| m.qc_flags.is_empty()));
+    }
+
+    #[test]
+    fn check_steps_flags_sudden_changes() {
+        let stations = [station("a", 0, Some(100))];
+        let mut history = History::default();
+        let mut previous = measurement("a", 10, 15.0);
+        previous.last_update = last_update(30);
+        previous.pressure = Some(1015.0);
+        history.record(&[previous], &stations, now());
+
+        let mut current = measurement("a", 75, 24.0);
+        current.pressure = Some(1018.0);
+        check_steps(&mut current, &history);
+        assert_eq!(
+            flags(&current),
+            [
+                ("temperature", QcFlag::StepChange),
+                ("wind_speed", QcFlag::StepChange),
+            ]
+        );
+    }
+
+    #[test]
+    fn check_steps_ignores_old_samples() {
+        let stations = [station("a", 0, Some(100))];
+        let mut history = History::default();
+        let mut previous = measurement("a", 10, 15.0);
+        previous.last_update = last_update(90);
+        history.record(&[previous], &stations, now());
+
+        let mut current = measurement("a", 75, 24.0);
+        check_steps(&mut current, &history);
+        assert!(current.qc_flags.is_empty());
+    }
+
+    #[test]
+    fn check_keeps_first_flag_of_field() {
+        let stations = [station("a", 0, Some(100))];
+        let mut measurements = vec![measurement("a", 250, 15.0)];
+        measurements[0].gusts_speed = Some(100);
+        check(&mut measurements, &stations, &History::default(), &CONFIG);
+        assert_eq!(
+            flags(&measurements[0]),
+            [
+                ("gusts_speed", QcFlag::GustBelowWind),
+                ("wind_speed", QcFlag::OutOfRange),
+            ]
+        );
+    }
+}
@@ -0,0 +1,387 @@
+use crate::common::{Measurement, Station};
+use crate::wind::{angle_between, direction_in_range, direction_label};
+
+use serde::{Deserialize, Serialize};
+
+// Conditions slightly outside of the limits make the site marginal rather than off.
+const DIRECTION_MARGIN: f64 = 22.5;
+const SPEED_MARGIN: f64 = 5.0;
+
+// Flying site with the conditions it's flyable in, e.g.
+//
+// {
+//   "name": "Àger",
+//   "station_ids": ["9648493fa0e6957dbe03eac2b18d1589"],
+//   "direction_min": 150,
+//   "direction_max": 240,
+//   "speed_min": 5,
+//   "speed_max": 25,
+//   "gusts_max": 30
+// }
+//
+// Range of directions may wrap around north, e.g. 300-30°.
+#[derive(Debug, Deserialize)]
+pub struct SiteConfig {
+    name: String,
+    // Stations that represent the site, e.g. the ones at take-off and landing.
+    pub station_ids: Vec<String>,
+    direction_min: f64,
+    direction_max: f64,
+    #[serde(default)]
+    speed_min: f64,
+    speed_max: f64,
+    gusts_max: Option<f64>,
+}
+
+// Ordered from the best to the worst.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, PartialOrd, Ord, Serialize)]
+#[serde(rename_all = "lowercase")]
+pub enum Status {
+    On,
+    Marginal,
+    Off,
+}
+
+#[derive(Debug, Serialize)]
+pub struct Site {
+    name: String,
+    status: Status,
+    reasons: Vec<String>,
+    station_ids: Vec<String>,
+}
+
+pub fn config() -> anyhow::Result<Vec<SiteConfig>> {
+    parse_config(&spin_sdk::variables::get("flying_sites")?)
+}
+
+fn parse_config(config: &str) -> anyhow::Result<Vec<SiteConfig>> {
+    if config.trim().is_empty() {
+        return Ok(vec![]);
+    }
+    let sites: Vec<SiteConfig> = serde_json::from_str(config)?;
+    for site in &sites {
+        site.validate()
+            .map_err(|e| anyhow::anyhow!("Invalid site '{}': {}", site.name, e))?;
+    }
+    Ok(sites)
+}
+
+impl SiteConfig {
+    fn validate(&self) -> anyhow::Result<()> {
+        if self.name.trim().is_empty() {
+            anyhow::bail!("name is empty");
+        }
+        if self.station_ids.is_empty() {
+            anyhow::bail!("station_ids is empty");
+        }
+        for direction in [self.direction_min, self.direction_max] {
+            if !(0.0..=360.0).contains(&direction) {
+                anyhow::bail!("direction {} out of 0-360°", direction);
+            }
+        }
+        if self.speed_min < 0.0 || self.speed_max <= self.speed_min {
+            anyhow::bail!(
+                "speed range {}-{} km/h is empty",
+                self.speed_min,
+                self.speed_max
+            );
+        }
+        if let Some(gusts_max) = self.gusts_max {
+            if gusts_max < self.speed_max {
+                anyhow::bail!(
+                    "gusts_max {} km/h below speed_max {} km/h",
+                    gusts_max,
+                    self.speed_max
+                );
+            }
+        }
+        Ok(())
+    }
+}
+
+// Station IDs are expected to be the current ones.
+pub fn evaluate(config: &SiteConfig, stations: &[Station], measurements: &[Measurement]) -> Site {
+    let mut status = Status::On;
+    let mut reasons = vec![];
+    let mut evaluated_count = 0;
+
+    for station_id in &config.station_ids {
+        // Station may be merged into the one of a preferred provider.
+        let Some(station) = stations
+            .iter()
+            .find(|station| &station.id == station_id || station.alternates.contains(station_id))
+        else {
+            continue;
+        };
+        let Some(measurement) = measurements
+            .iter()
+            .find(|measurement| measurement.station_id == station.id)
+        else {
+            continue;
+        };
+        evaluated_count += 1;
+        for (station_status, reason) in evaluate_measurement(config, measurement) {
+            status = status.max(station_status);
+            reasons.push(format!("{}: {}", station.name, reason));
+        }
+    }
+
+    if evaluated_count == 0 {
+        status = Status::Off;
+        reasons.push("No recent measurements".to_owned());
+    }
+
+    Site {
+        name: config.name.to_owned(),
+        status,
+        reasons,
+        station_ids: config.station_ids.to_owned(),
+    }
+}
+
+// Returns the limits not met, with how bad it is.
+fn evaluate_measurement(config: &SiteConfig, measurement: &Measurement) -> Vec<(Status, String)> {
+    let mut results = vec![];
+    let wind_speed = measurement.wind_speed as f64;
+
+    match measurement.wind_direction {
+        Some(direction)
+            if !direction_in_range(direction, config.direction_min, config.direction_max) =>
+        {
+            let distance = angle_between(direction, config.direction_min)
+                .min(angle_between(direction, config.direction_max));
+            let status = if distance <= DIRECTION_MARGIN {
+                Status::Marginal
+            } else {
+                Status::Off
+            };
+            results.push((
+                status,
+                format!(
+                    "wind from {} ({}°) out of {}-{}°",
+                    direction_label(direction),
+                    direction,
+                    config.direction_min,
+                    config.direction_max
+                ),
+            ));
+        }
+        Some(_) => {}
+        None if wind_speed > 0.0 => {
+            results.push((Status::Marginal, "wind direction unknown".to_owned()))
+        }
+        None => {}
+    }
+
+    if wind_speed < config.speed_min {
+        let status = if config.speed_min - wind_speed <= SPEED_MARGIN {
+            Status::Marginal
+        } else {
+            Status::Off
+        };
+        results.push((
+            status,
+            format!("wind {} km/h below {} km/h", wind_speed, config.speed_min),
+        ));
+    }
+    if wind_speed > config.speed_max {
+        let status = if wind_speed - config.speed_max <= SPEED_MARGIN {
+            Status::Marginal
+        } else {
+            Status::Off
+        };
+        results.push((
+            status,
+            format!("wind {} km/h above {} km/h", wind_speed, config.speed_max),
+        ));
+    }
+
+    if let (Some(gusts_speed), Some(gusts_max)) = (measurement.gusts_speed, config.gusts_max) {
+        let gusts_speed = gusts_speed as f64;
+        if gusts_speed > gusts_max {
+            results.push((
+                Status::Off,
+                format!("gusts {} km/h above {} km/h", gusts_speed, gusts_max),
+            ));
+        } else if gusts_speed > gusts_max - SPEED_MARGIN {
+            results.push((
+                Status::Marginal,
+                format!("gusts {} km/h close to {} km/h", gusts_speed, gusts_max),
+            ));
+        }
+    }
+
+    if measurement.stale {
+        results.push((Status::Off, "measurement is stale".to_owned()));
+    }
+    if !measurement.qc_flags.is_empty() {
+        results.push((
+            Status::Marginal,
+            "measurement failed the quality control".to_owned(),
+        ));
+    }
+
+    results
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const AGER: &str = r#"[{"name": "Àger", "station_ids": ["a", "b"], "direction_min": 150,
+        "direction_max": 240, "speed_min": 5, "speed_max": 25, "gusts_max": 30}]"#;
+
+    // Site facing north, with the range of directions wrapping through it.
+    const NORTH: &str = r#"[{"name": "Nord", "station_ids": ["a"], "direction_min": 300,
+        "direction_max": 30, "speed_max": 20}]"#;
+
+    fn site(config: &str) -> SiteConfig {
+        parse_config(config).unwrap().remove(0)
+    }
+
+    fn station(id: &str, alternates: &[&str]) -> Station {
+        Station {
+            id: id.to_owned(),
+            name: id.to_uppercase(),
+            elevation: None,
+            url: String::new(),
+            lat: 42.0,
+            long: 0.7,
+            available: true,
+            provider: "meteocat".to_owned(),
+            vendor_id: id.to_owned(),
+            alternates: alternates.iter().map(|id| id.to_string()).collect(),
+        }
+    }
+
+    fn measurement(station_id: &str, speed: u64, direction: f64, gusts: u64) -> Measurement {
+        Measurement {
+            station_id: station_id.to_owned(),
+            wind_speed: speed,
+            wind_direction: Some(direction),
+            gusts_speed: Some(gusts),
+            ..Default::default()
+        }
+    }
+
+    fn statuses(config: &SiteConfig, measurement: &Measurement) -> Vec<Status> {
+        evaluate_measurement(config, measurement)
+            .into_iter()
+            .map(|(status, _)| status)
+            .collect()
+    }
+
+    #[test]
+    fn evaluate_measurement_within_limits() {
+        let config = site(AGER);
+        assert!(statuses(&config, &measurement("a", 15, 200.0, 20)).is_empty());
+    }
+
+    #[test]
+    fn evaluate_measurement_speed_margins() {
+        let config = site(AGER);
+        assert_eq!(
+            statuses(&config, &measurement("a", 30, 200.0, 0)),
+            [Status::Marginal]
+        );
+        assert_eq!(
+            statuses(&config, &measurement("a", 31, 200.0, 0)),
+            [Status::Off]
+        );
+        assert_eq!(
+            statuses(&config, &measurement("a", 0, 200.0, 5)),
+            [Status::Marginal]
+        );
+        assert_eq!(
+            statuses(&config, &measurement("a", 15, 200.0, 26)),
+            [Status::Marginal]
+        );
+        assert_eq!(
+            statuses(&config, &measurement("a", 15, 200.0, 31)),
+            [Status::Off]
+        );
+    }
+
+    #[test]
+    fn evaluate_measurement_direction_margins() {
+        let config = site(AGER);
+        assert_eq!(
+            statuses(&config, &measurement("a", 15, 130.0, 20)),
+            [Status::Marginal]
+        );
+        assert_eq!(
+            statuses(&config, &measurement("a", 15, 100.0, 20)),
+            [Status::Off]
+        );
+    }
+
+    #[test]
+    fn evaluate_measurement_range_through_north() {
+        let config = site(NORTH);
+        for direction in [300.0, 350.0, 0.0, 10.0, 30.0] {
+            assert!(statuses(&config, &measurement("a", 10, direction, 15)).is_empty());
+        }
+        assert_eq!(
+            statuses(&config, &measurement("a", 10, 45.0, 15)),
+            [Status::Marginal]
+        );
+        assert_eq!(
+            statuses(&config, &measurement("a", 10, 280.0, 15)),
+            [Status::Marginal]
+        );
+        assert_eq!(
+            statuses(&config, &measurement("a", 10, 180.0, 15)),
+            [Status::Off]
+        );
+    }
+
+    #[test]
+    fn evaluate_takes_worst_station() {
+        let config = site(AGER);
+        let stations = [station("a", &[]), station("b", &[])];
+        let measurements = [
+            measurement("a", 15, 200.0, 20),
+            measurement("b", 40, 200.0, 45),
+        ];
+        let site = evaluate(&config, &stations, &measurements);
+        assert_eq!(site.status, Status::Off);
+        assert_eq!(site.reasons.len(), 2);
+        assert!(site.reasons[0].starts_with("B: "));
+    }
+
+    #[test]
+    fn evaluate_finds_merged_station() {
+        let config = site(AGER);
+        // Station "b" was merged into "c" of a preferred provider.
+        let stations = [station("c", &["b"])];
+        let measurements = [measurement("c", 15, 200.0, 20)];
+        let site = evaluate(&config, &stations, &measurements);
+        assert_eq!(site.status, Status::On);
+        assert!(site.reasons.is_empty());
+    }
+
+    #[test]
+    fn evaluate_without_measurements() {
+        let config = site(AGER);
+        let site = evaluate(&config, &[station("a", &[])], &[]);
+        assert_eq!(site.status, Status::Off);
+        assert_eq!(site.reasons, ["No recent measurements"]);
+    }
+
+    #[test]
+    fn parse_config_rejects_invalid_ranges() {
+        let invalid = [
+            r#"[{"name": "", "station_ids": ["a"], "direction_min": 0, "direction_max": 90, "speed_max": 20}]"#,
+            r#"[{"name": "X", "station_ids": [], "direction_min": 0, "direction_max": 90, "speed_max": 20}]"#,
+            r#"[{"name": "X", "station_ids": ["a"], "direction_min": -10, "direction_max": 90, "speed_max": 20}]"#,
+            r#"[{"name": "X", "station_ids": ["a"], "direction_min": 0, "direction_max": 400, "speed_max": 20}]"#,
+            r#"[{"name": "X", "station_ids": ["a"], "direction_min": 0, "direction_max": 90, "speed_min": 25, "speed_max": 20}]"#,
+            r#"[{"name": "X", "station_ids": ["a"], "direction_min": 0, "direction_max": 90, "speed_max": 20, "gusts_max": 15}]"#,
+        ];
+        for config in invalid {
+            assert!(parse_config(config).is_err(), "{}", config);
+        }
+        assert!(parse_config("").unwrap().is_empty());
+        assert_eq!(parse_config(AGER).unwrap().len(), 1);
+    }
+}
@@ -0,0 +1,209 @@
+use crate::common::{parse_last_update, Measurement, Station};
+
+use chrono::{DateTime, Duration, Utc};
+use std::collections::{HashMap, HashSet};
+
+pub struct StaleConfig {
+    pub max_age: Duration,
+    // Max age for the specific providers, e.g. "meteoclimatic".
+    pub max_age_overrides: HashMap<String, Duration>,
+}
+
+pub fn config() -> anyhow::Result<StaleConfig> {
+    let max_age_minutes: i64 = spin_sdk::variables::get("stale_max_age_minutes")?
+        .trim()
+        .parse()?;
+    let max_age_overrides = spin_sdk::variables::get("stale_max_age_overrides")?
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let (provider, minutes) = entry.split_once('=').ok_or_else(|| {
+                anyhow::anyhow!("Invalid max age '{}', 'provider=minutes' expected", entry)
+            })?;
+            let minutes: i64 = minutes.trim().parse()?;
+            Ok((provider.trim().to_owned(), Duration::minutes(minutes)))
+        })
+        .collect::<anyhow::Result<HashMap<String, Duration>>>()?;
+    Ok(StaleConfig {
+        max_age: Duration::minutes(max_age_minutes),
+        max_age_overrides,
+    })
+}
+
+impl StaleConfig {
+    fn max_age(&self, provider: &str) -> Duration {
+        self.max_age_overrides
+            .get(provider)
+            .or_else(|| {
+                // Custom sources may be configured all together as "custom".
+                let provider = provider.split('/').next()?;
+                self.max_age_overrides.get(provider)
+            })
+            .cloned()
+            .unwrap_or(self.max_age)
+    }
+}
+
+// Drops the measurements older than allowed, or marks them as stale if they are requested.
+// Stations of the dropped measurements are dropped too, so that they don't
+// show up without anything recent to display.
+pub fn filter_stale(
+    stations: Vec<Station>,
+    measurements: Vec<Measurement>,
+    config: &StaleConfig,
+    now: DateTime<Utc>,
+    include_stale: bool,
+) -> (Vec<Station>, Vec<Measurement>) {
+    let providers: HashMap<&String, &String> = stations
+        .iter()
+        .map(|station| (&station.id, &station.provider))
+        .collect();
+
+    let mut stale_station_ids = HashSet::new();
+    let measurements: Vec<Measurement> = measurements
+        .into_iter()
+        .filter_map(|mut measurement| {
+            let provider = providers
+                .get(&measurement.station_id)
+                .map(|provider| provider.as_str())
+                .unwrap_or("");
+            let Some(last_update) = parse_last_update(&measurement.last_update) else {
+                println!(
+                    "[{}] Invalid last update: {}",
+                    measurement.station_id, measurement.last_update
+                );
+                return Some(measurement);
+            };
+            if now - last_update <= config.max_age(provider) {
+                return Some(measurement);
+            }
+            stale_station_ids.insert(measurement.station_id.to_owned());
+            measurement.stale = true;
+            include_stale.then_some(measurement)
+        })
+        .collect();
+
+    if stale_station_ids.is_empty() {
+        return (stations, measurements);
+    }
+    println!("Found {} stale measurements", stale_station_ids.len());
+    let stations = if include_stale {
+        stations
+    } else {
+        stations
+            .into_iter()
+            .filter(|station| !stale_station_ids.contains(&station.id))
+            .collect()
+    };
+    (stations, measurements)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn now() -> DateTime<Utc> {
+        DateTime::parse_from_rfc3339("2024-05-12T12:00:00Z")
+            .unwrap()
+            .with_timezone(&Utc)
+    }
+
+    fn config() -> StaleConfig {
+        StaleConfig {
+            max_age: Duration::minutes(180),
+            max_age_overrides: HashMap::from([
+                ("meteoclimatic".to_owned(), Duration::minutes(90)),
+                ("custom".to_owned(), Duration::minutes(30)),
+            ]),
+        }
+    }
+
+    fn station(id: &str, provider: &str) -> Station {
+        Station {
+            id: id.to_owned(),
+            name: id.to_uppercase(),
+            elevation: None,
+            url: String::new(),
+            lat: 42.0,
+            long: 2.0,
+            available: true,
+            provider: provider.to_owned(),
+            vendor_id: id.to_owned(),
+            alternates: vec![],
+        }
+    }
+
+    fn measurement(station_id: &str, minutes_ago: i64) -> Measurement {
+        Measurement {
+            station_id: station_id.to_owned(),
+            last_update: (now() - Duration::minutes(minutes_ago))
+                .format("%Y-%m-%dT%H:%MZ")
+                .to_string(),
+            ..Default::default()
+        }
+    }
+
+    fn station_ids(stations: &[Station]) -> Vec<&str> {
+        stations.iter().map(|station| station.id.as_str()).collect()
+    }
+
+    fn measurement_ids(measurements: &[Measurement]) -> Vec<(&str, bool)> {
+        measurements
+            .iter()
+            .map(|measurement| (measurement.station_id.as_str(), measurement.stale))
+            .collect()
+    }
+
+    #[test]
+    fn max_age_of_providers() {
+        let config = config();
+        assert_eq!(config.max_age("meteocat"), Duration::minutes(180));
+        assert_eq!(config.max_age("meteoclimatic"), Duration::minutes(90));
+        assert_eq!(config.max_age("custom/club"), Duration::minutes(30));
+    }
+
+    #[test]
+    fn filter_stale_drops_measurements_and_their_stations() {
+        let stations = vec![
+            station("a", "meteocat"),
+            station("b", "meteoclimatic"),
+            station("c", "meteocat"),
+            station("d", "meteocat"),
+        ];
+        let measurements = vec![
+            measurement("a", 120),
+            measurement("b", 120),
+            measurement("c", 200),
+        ];
+        let (stations, measurements) =
+            filter_stale(stations, measurements, &config(), now(), false);
+        // Station without any measurement isn't known to be stale.
+        assert_eq!(station_ids(&stations), ["a", "d"]);
+        assert_eq!(measurement_ids(&measurements), [("a", false)]);
+    }
+
+    #[test]
+    fn filter_stale_marks_included_measurements() {
+        let stations = vec![station("a", "meteocat"), station("b", "meteoclimatic")];
+        let measurements = vec![measurement("a", 120), measurement("b", 120)];
+        let (stations, measurements) = filter_stale(stations, measurements, &config(), now(), true);
+        assert_eq!(station_ids(&stations), ["a", "b"]);
+        assert_eq!(measurement_ids(&measurements), [("a", false), ("b", true)]);
+    }
+
+    #[test]
+    fn filter_stale_keeps_invalid_last_update() {
+        let mut invalid = measurement("a", 0);
+        invalid.last_update = "yesterday".to_owned();
+        let (stations, measurements) = filter_stale(
+            vec![station("a", "meteocat")],
+            vec![invalid],
+            &config(),
+            now(),
+            false,
+        );
+        assert_eq!(station_ids(&stations), ["a"]);
+        assert_eq!(measurement_ids(&measurements), [("a", false)]);
+    }
+}
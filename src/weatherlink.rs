@@ -0,0 +1,274 @@
+use crate::common::{
+    fahrenheit_to_celsius, feet_to_m, fetch_utf8_with_header, inches_to_mm, inhg_to_hpa,
+    mph_to_kmh, station_id_of, Measurement, Station,
+};
+
+use chrono::DateTime;
+use serde::Deserialize;
+use serde_json::Value;
+use std::collections::HashMap;
+
+const API_URL: &str = "https://api.weatherlink.com/v2";
+const STATION_BASE_URL: &str = "https://www.weatherlink.com/bulletin";
+
+#[derive(Debug, Deserialize)]
+struct StationRaw {
+    station_id: u64,
+    station_id_uuid: Option<String>,
+    station_name: String,
+    latitude: f64,
+    longitude: f64,
+    // In feet.
+    elevation: Option<f64>,
+}
+
+#[derive(Debug, Deserialize)]
+struct StationsRaw {
+    stations: Vec<StationRaw>,
+}
+
+// Fields of the data records depend on the type of the sensor.
+#[derive(Debug, Deserialize)]
+struct SensorRaw {
+    data: Vec<HashMap<String, Value>>,
+}
+
+#[derive(Debug, Deserialize)]
+struct CurrentRaw {
+    station_id: u64,
+    sensors: Vec<SensorRaw>,
+}
+
+// Names of the fields of the current consoles first, then of the older
+// Vantage consoles. Speeds in mph, temperature in °F, pressure in inHg and
+// precipitation in inches.
+const WIND_SPEED: [&str; 2] = ["wind_speed_last", "wind_speed"];
+const WIND_DIRECTION: [&str; 2] = ["wind_dir_last", "wind_dir"];
+const GUSTS_SPEED: [&str; 2] = ["wind_speed_hi_last_10_min", "wind_gust_10_min"];
+const TEMPERATURE: [&str; 2] = ["temp", "temp_out"];
+const HUMIDITY: [&str; 2] = ["hum", "hum_out"];
+const PRESSURE: [&str; 2] = ["bar_sea_level", "bar"];
+const PRECIPITATION: [&str; 2] = ["rainfall_daily_in", "rain_day_in"];
+
+// Every station comes with the API key and secret of its owner's account.
+pub struct StationConfig {
+    station_id: String,
+    api_key: String,
+    api_secret: String,
+}
+
+// Parses "STATION_ID:API_KEY:API_SECRET,STATION_ID:API_KEY:API_SECRET,...".
+pub fn config() -> anyhow::Result<Vec<StationConfig>> {
+    spin_sdk::variables::get("weatherlink_stations")?
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| {
+            let parts: Vec<&str> = entry.splitn(3, ':').map(|part| part.trim()).collect();
+            let [station_id, api_key, api_secret] = parts[..] else {
+                anyhow::bail!("Invalid station entry, 'STATION_ID:API_KEY:API_SECRET' expected");
+            };
+            Ok(StationConfig {
+                station_id: station_id.to_owned(),
+                api_key: api_key.to_owned(),
+                api_secret: api_secret.to_owned(),
+            })
+        })
+        .collect()
+}
+
+pub async fn fetch_data(
+    config: &[StationConfig],
+) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+    println!("[weatherlink] Fetching...");
+    let mut available_stations: Vec<Station> = vec![];
+    let mut measurements: Vec<Measurement> = vec![];
+
+    for station_config in config {
+        match fetch_station(station_config).await {
+            Ok(Some((station, measurement))) => {
+                available_stations.push(station);
+                measurements.push(measurement);
+            }
+            Ok(None) => println!(
+                "[{}] Wind information not available",
+                station_config.station_id
+            ),
+            Err(e) => println!("[{}] {}", station_config.station_id, e),
+        }
+    }
+
+    Ok((available_stations, measurements))
+}
+
+// The secret is sent in a header, so that it doesn't end up in logs of URLs.
+async fn fetch_station(config: &StationConfig) -> anyhow::Result<Option<(Station, Measurement)>> {
+    let fetch = |endpoint: &str| {
+        let url = format!(
+            "{}/{}/{}?api-key={}",
+            API_URL, endpoint, config.station_id, config.api_key
+        );
+        async move { fetch_utf8_with_header(&url, "X-Api-Secret", &config.api_secret).await }
+    };
+    let stations_body = fetch("stations").await?;
+    let current_body = fetch("current").await?;
+    parse_station(&stations_body, &current_body)
+}
+
+fn parse_station(
+    stations_body: &str,
+    current_body: &str,
+) -> anyhow::Result<Option<(Station, Measurement)>> {
+    let stations_raw: StationsRaw = serde_json::from_str(stations_body)?;
+    let current_raw: CurrentRaw = serde_json::from_str(current_body)?;
+    let station_raw = stations_raw
+        .stations
+        .into_iter()
+        .find(|station| station.station_id == current_raw.station_id)
+        .ok_or_else(|| anyhow::anyhow!("Station details unavailable"))?;
+    convert(station_raw, current_raw)
+}
+
+// First value of any of the fields among the records of all the sensors.
+fn number(records: &[&HashMap<String, Value>], fields: &[&str]) -> Option<f64> {
+    fields.iter().find_map(|field| {
+        records
+            .iter()
+            .find_map(|record| record.get(*field).and_then(Value::as_f64))
+    })
+}
+
+fn convert(
+    station_raw: StationRaw,
+    current_raw: CurrentRaw,
+) -> anyhow::Result<Option<(Station, Measurement)>> {
+    let records: Vec<&HashMap<String, Value>> = current_raw
+        .sensors
+        .iter()
+        .flat_map(|sensor| sensor.data.iter())
+        .collect();
+
+    // Time of the record with the wind, the other sensors may report less often.
+    let Some((wind_speed, ts)) = records.iter().find_map(|record| {
+        let wind_speed = number(&[record], &WIND_SPEED)?;
+        Some((wind_speed, record.get("ts")?.as_i64()?))
+    }) else {
+        return Ok(None);
+    };
+    let wind_speed = mph_to_kmh(wind_speed).round() as u64;
+    let last_update = DateTime::from_timestamp(ts, 0)
+        .ok_or_else(|| anyhow::anyhow!("Invalid time {}", ts))?
+        .format("%Y-%m-%dT%H:%MZ")
+        .to_string();
+
+    let vendor_id = station_raw.station_id.to_string();
+    let station_url = match &station_raw.station_id_uuid {
+        Some(uuid) => format!("{}/{}", STATION_BASE_URL, uuid),
+        None => format!("urn:zephyr-data-provider:weatherlink:{}", vendor_id),
+    };
+    let station_id = station_id_of("weatherlink", &vendor_id);
+
+    let measurement = Measurement {
+        station_id: station_id.clone(),
+        wind_speed,
+        wind_direction: number(&records, &WIND_DIRECTION).filter(|_| wind_speed != 0),
+        gusts_speed: number(&records, &GUSTS_SPEED).map(|v| mph_to_kmh(v).round() as u64),
+        temperature: number(&records, &TEMPERATURE).map(fahrenheit_to_celsius),
+        humidity: number(&records, &HUMIDITY),
+        pressure: number(&records, &PRESSURE).map(inhg_to_hpa),
+        precipitation: number(&records, &PRECIPITATION).map(inches_to_mm),
+        last_update,
+        ..Default::default()
+    };
+    let station = Station {
+        id: station_id,
+        name: station_raw.station_name.to_owned(),
+        elevation: station_raw
+            .elevation
+            .map(|elevation| feet_to_m(elevation).round() as i64),
+        url: station_url,
+        lat: station_raw.latitude,
+        long: station_raw.longitude,
+        available: true,
+        provider: "weatherlink".to_owned(),
+        vendor_id,
+        alternates: vec![],
+    };
+    Ok(Some((station, measurement)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    const STATIONS: &str = include_str!("../tests/fixtures/weatherlink_stations.json");
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("value expected");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn parse_station_converts_imperial_units() {
+        let current = include_str!("../tests/fixtures/weatherlink_current.json");
+        let (station, measurement) = parse_station(STATIONS, current).unwrap().unwrap();
+
+        assert_eq!(station.id, station_id_of("weatherlink", "117023"));
+        assert_eq!(station.name, "Club Organyà");
+        assert_eq!(
+            station.url,
+            "https://www.weatherlink.com/bulletin/5e1b07a4-82d4-4b8c-9f0e-2c8f4e0d7b61"
+        );
+        // 5184 ft
+        assert_eq!(station.elevation, Some(1580));
+        assert_eq!((station.lat, station.long), (42.2178, 1.3291));
+
+        assert_eq!(measurement.last_update, "2024-05-12T10:15Z");
+        // 10 mph and 18.5 mph
+        assert_eq!(measurement.wind_speed, 16);
+        assert_eq!(measurement.gusts_speed, Some(30));
+        assert_eq!(measurement.wind_direction, Some(315.0));
+        // 59 °F, 30.01 inHg of the barometer sensor and 0.12 in
+        assert_close(measurement.temperature, 15.0);
+        assert_close(measurement.pressure, 1016.25);
+        assert_close(measurement.precipitation, 3.05);
+        assert_eq!(measurement.humidity, Some(48.0));
+    }
+
+    #[test]
+    fn parse_station_of_vantage_console() {
+        let current = include_str!("../tests/fixtures/weatherlink_current_vantage.json");
+        let (station, measurement) = parse_station(STATIONS, current).unwrap().unwrap();
+
+        assert_eq!(station.vendor_id, "48211");
+        assert_eq!(station.url, "urn:zephyr-data-provider:weatherlink:48211");
+        assert_eq!(station.elevation, None);
+
+        assert_eq!(measurement.last_update, "2024-05-12T10:10Z");
+        assert_eq!(measurement.wind_speed, 8);
+        assert_eq!(measurement.gusts_speed, Some(14));
+        assert_eq!(measurement.wind_direction, Some(200.0));
+        assert_close(measurement.temperature, 20.0);
+        assert_close(measurement.pressure, 1013.21);
+        assert_eq!(measurement.humidity, Some(60.0));
+        assert_eq!(measurement.precipitation, Some(0.0));
+    }
+
+    #[test]
+    fn parse_station_skips_station_without_wind() {
+        let current = r#"{"station_id": 117023, "sensors": [{"lsid": 1, "sensor_type": 242,
+            "data_structure_type": 12, "data": [{"ts": 1715508900, "bar_sea_level": 30.01}]}]}"#;
+        assert!(parse_station(STATIONS, current).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_station_requires_station_details() {
+        let current = r#"{"station_id": 1, "sensors": []}"#;
+        assert!(parse_station(STATIONS, current).is_err());
+    }
+}
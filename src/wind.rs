@@ -0,0 +1,322 @@
+use crate::common::{parse_last_update, Measurement, WindDetails};
+use crate::history::Sample;
+
+use chrono::Duration;
+
+const LABELS: [&str; 16] = [
+    "N", "NNE", "NE", "ENE", "E", "ESE", "SE", "SSE", "S", "SSW", "SW", "WSW", "W", "WNW", "NW",
+    "NNW",
+];
+
+const DIRECTIONS_CA: [&str; 16] = [
+    "nord",
+    "nord-nord-est",
+    "nord-est",
+    "est-nord-est",
+    "est",
+    "est-sud-est",
+    "sud-est",
+    "sud-sud-est",
+    "sud",
+    "sud-sud-oest",
+    "sud-oest",
+    "oest-sud-oest",
+    "oest",
+    "oest-nord-oest",
+    "nord-oest",
+    "nord-nord-oest",
+];
+
+const DIRECTIONS_ES: [&str; 16] = [
+    "norte",
+    "nornordeste",
+    "nordeste",
+    "estenordeste",
+    "este",
+    "estesudeste",
+    "sudeste",
+    "sudsudeste",
+    "sur",
+    "sudsudoeste",
+    "sudoeste",
+    "oestesudoeste",
+    "oeste",
+    "oestenoroeste",
+    "noroeste",
+    "nornoroeste",
+];
+
+// Lowest speed of Beaufort forces 1 to 12, in km/h.
+const BEAUFORT_LIMITS: [f64; 12] = [
+    1.0, 6.0, 12.0, 20.0, 29.0, 39.0, 50.0, 62.0, 75.0, 89.0, 103.0, 118.0,
+];
+
+const BEAUFORT_CA: [&str; 13] = [
+    "Calma",
+    "Ventolina",
+    "Brisa molt feble",
+    "Brisa feble",
+    "Brisa moderada",
+    "Brisa fresca",
+    "Brisa forta",
+    "Vent fort",
+    "Temporal",
+    "Temporal fort",
+    "Temporal molt fort",
+    "Tempesta",
+    "Huracà",
+];
+
+const BEAUFORT_ES: [&str; 13] = [
+    "Calma",
+    "Ventolina",
+    "Flojito",
+    "Flojo",
+    "Bonancible",
+    "Fresquito",
+    "Fresco",
+    "Frescachón",
+    "Temporal",
+    "Temporal fuerte",
+    "Temporal duro",
+    "Temporal muy duro",
+    "Temporal huracanado",
+];
+
+// Directions are averaged over the samples of the last hour.
+const MEAN_WINDOW_MINUTES: i64 = 60;
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub enum Lang {
+    Catalan,
+    Spanish,
+}
+
+impl Lang {
+    pub fn parse(lang: &str) -> Option<Lang> {
+        match lang {
+            "ca" => Some(Lang::Catalan),
+            "es" => Some(Lang::Spanish),
+            _ => None,
+        }
+    }
+}
+
+// Index of the closest of the 16 compass points.
+fn compass_point(direction: f64) -> usize {
+    (direction.rem_euclid(360.0) / 22.5).round() as usize % 16
+}
+
+pub fn direction_label(direction: f64) -> &'static str {
+    LABELS[compass_point(direction)]
+}
+
+pub fn beaufort(speed_kmh: f64) -> u8 {
+    BEAUFORT_LIMITS
+        .iter()
+        .take_while(|limit| speed_kmh >= **limit)
+        .count() as u8
+}
+
+// E.g. "Brisa feble de l'est-nord-est", "Flojo del nordeste".
+pub fn description(speed_kmh: f64, direction: Option<f64>, lang: Lang) -> String {
+    let force = beaufort(speed_kmh) as usize;
+    let (forces, directions) = match lang {
+        Lang::Catalan => (BEAUFORT_CA, DIRECTIONS_CA),
+        Lang::Spanish => (BEAUFORT_ES, DIRECTIONS_ES),
+    };
+    let Some(direction) = direction.filter(|_| force > 0) else {
+        return forces[force].to_owned();
+    };
+    let direction = directions[compass_point(direction)];
+    let preposition = match lang {
+        Lang::Catalan if direction.starts_with(['a', 'e', 'i', 'o', 'u']) => "de l'",
+        Lang::Catalan | Lang::Spanish => "del ",
+    };
+    format!("{} {}{}", forces[force], preposition, direction)
+}
+
+// Smallest angle between two directions, in degrees.
+pub fn angle_between(a: f64, b: f64) -> f64 {
+    let difference = (a - b).rem_euclid(360.0);
+    difference.min(360.0 - difference)
+}
+
+// Range of directions may wrap around north, e.g. 300-30°.
+pub fn direction_in_range(direction: f64, min: f64, max: f64) -> bool {
+    if min <= max {
+        (min..=max).contains(&direction)
+    } else {
+        direction >= min || direction <= max
+    }
+}
+
+// Vector average of directions in degrees, weighted e.g. by speeds.
+// None when the directions cancel each other out.
+pub fn circular_mean(samples: impl IntoIterator<Item = (f64, f64)>) -> Option<f64> {
+    let (mut x, mut y, mut total_weight) = (0.0, 0.0, 0.0);
+    for (direction, weight) in samples {
+        let direction = direction.to_radians();
+        x += weight * direction.sin();
+        y += weight * direction.cos();
+        total_weight += weight;
+    }
+    if total_weight <= 0.0 || x.hypot(y) / total_weight < 1e-6 {
+        return None;
+    }
+    Some(x.atan2(y).to_degrees().rem_euclid(360.0))
+}
+
+// Circular standard deviation of directions in degrees, sqrt(-2 ln R)
+// where R is the length of the mean unit vector. None without directions.
+pub fn circular_std_dev(directions: impl IntoIterator<Item = f64>) -> Option<f64> {
+    let (mut x, mut y, mut count) = (0.0, 0.0, 0);
+    for direction in directions {
+        let direction = direction.to_radians();
+        x += direction.sin();
+        y += direction.cos();
+        count += 1;
+    }
+    if count == 0 {
+        return None;
+    }
+    let resultant_length = (x.hypot(y) / count as f64).clamp(f64::MIN_POSITIVE, 1.0);
+    Some((-2.0 * resultant_length.ln()).sqrt().to_degrees())
+}
+
+pub fn details(measurement: &Measurement, samples: &[Sample], lang: Lang) -> WindDetails {
+    let wind_speed = measurement.wind_speed as f64;
+
+    let direction_mean = parse_last_update(&measurement.last_update).and_then(|time| {
+        let since = time - Duration::minutes(MEAN_WINDOW_MINUTES);
+        circular_mean(samples.iter().filter_map(|sample| {
+            let sample_time = sample.time()?;
+            if sample_time <= since || sample_time > time {
+                return None;
+            }
+            Some((sample.wind_direction?, sample.wind_speed? as f64))
+        }))
+    });
+
+    WindDetails {
+        wind_direction_label: measurement.wind_direction.map(direction_label),
+        beaufort: beaufort(wind_speed),
+        wind_description: description(wind_speed, measurement.wind_direction, lang),
+        wind_direction_1h: direction_mean.map(|direction| direction.round()),
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    #[test]
+    fn direction_label_of_compass_points() {
+        let cases = [
+            (0.0, "N"),
+            (11.24, "N"),
+            (11.25, "NNE"),
+            (45.0, "NE"),
+            (90.0, "E"),
+            (180.0, "S"),
+            (202.5, "SSW"),
+            (270.0, "W"),
+            (348.74, "NNW"),
+            (348.75, "N"),
+            (359.9, "N"),
+            (360.0, "N"),
+        ];
+        for (direction, label) in cases {
+            assert_eq!(direction_label(direction), label, "{}", direction);
+        }
+    }
+
+    #[test]
+    fn beaufort_scale_boundaries() {
+        let cases = [
+            (0.0, 0),
+            (0.9, 0),
+            (1.0, 1),
+            (5.9, 1),
+            (6.0, 2),
+            (11.9, 2),
+            (12.0, 3),
+            (20.0, 4),
+            (29.0, 5),
+            (39.0, 6),
+            (50.0, 7),
+            (62.0, 8),
+            (75.0, 9),
+            (89.0, 10),
+            (103.0, 11),
+            (117.9, 11),
+            (118.0, 12),
+            (200.0, 12),
+        ];
+        for (speed, force) in cases {
+            assert_eq!(beaufort(speed), force, "{}", speed);
+        }
+    }
+
+    #[test]
+    fn description_of_calm() {
+        assert_eq!(description(0.0, None, Lang::Catalan), "Calma");
+        assert_eq!(description(0.5, Some(90.0), Lang::Spanish), "Calma");
+    }
+
+    #[test]
+    fn description_of_wind() {
+        assert_eq!(
+            description(15.0, Some(67.5), Lang::Catalan),
+            "Brisa feble de l'est-nord-est"
+        );
+        assert_eq!(
+            description(15.0, Some(0.0), Lang::Catalan),
+            "Brisa feble del nord"
+        );
+        assert_eq!(
+            description(15.0, Some(45.0), Lang::Spanish),
+            "Flojo del nordeste"
+        );
+        assert_eq!(description(15.0, None, Lang::Spanish), "Flojo");
+    }
+
+    #[test]
+    fn circular_mean_wraps_around_north() {
+        let mean = circular_mean([(350.0, 1.0), (10.0, 1.0)]).unwrap();
+        assert!(angle_between(mean, 0.0) < 1e-9, "{}", mean);
+    }
+
+    #[test]
+    fn circular_mean_is_weighted() {
+        let mean = circular_mean([(0.0, 1.0), (90.0, 3.0)]).unwrap();
+        assert!((mean - 71.57).abs() < 0.01, "{}", mean);
+    }
+
+    #[test]
+    fn circular_mean_of_opposite_directions() {
+        assert_eq!(circular_mean([(90.0, 1.0), (270.0, 1.0)]), None);
+        assert_eq!(circular_mean([(90.0, 0.0)]), None);
+        assert_eq!(circular_mean([]), None);
+    }
+
+    #[test]
+    fn circular_std_dev_of_identical_directions() {
+        assert_eq!(circular_std_dev([270.0, 270.0, 270.0]), Some(0.0));
+    }
+
+    #[test]
+    fn circular_std_dev_wraps_around_north() {
+        let spread = circular_std_dev([350.0, 10.0]).unwrap();
+        assert!((spread - 10.0).abs() < 0.1, "{}", spread);
+        assert_eq!(
+            circular_std_dev([350.0, 10.0]).map(f64::round),
+            circular_std_dev([170.0, 190.0]).map(f64::round)
+        );
+    }
+
+    #[test]
+    fn circular_std_dev_of_nothing() {
+        assert_eq!(circular_std_dev([]), None);
+    }
+}
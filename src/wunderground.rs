@@ -0,0 +1,197 @@
+use crate::common::{
+    fahrenheit_to_celsius, feet_to_m, fetch_utf8, inches_to_mm, inhg_to_hpa, mph_to_kmh,
+    station_id_of, Measurement, Station,
+};
+
+use chrono::{DateTime, Utc};
+use serde::Deserialize;
+
+const API_URL: &str = "https://api.weather.com/v2/pws/observations/current";
+const STATION_BASE_URL: &str = "https://www.wunderground.com/dashboard/pws";
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct ImperialRaw {
+    temp: Option<f64>,
+    windSpeed: Option<f64>,
+    windGust: Option<f64>,
+    pressure: Option<f64>,
+    precipTotal: Option<f64>,
+    elev: Option<f64>,
+}
+
+#[allow(non_snake_case)]
+#[derive(Debug, Deserialize)]
+struct ObservationRaw {
+    stationID: String,
+    obsTimeUtc: String,
+    neighborhood: Option<String>,
+    lat: f64,
+    lon: f64,
+    winddir: Option<f64>,
+    humidity: Option<f64>,
+    imperial: ImperialRaw,
+}
+
+#[derive(Debug, Deserialize)]
+struct CurrentRaw {
+    observations: Vec<ObservationRaw>,
+}
+
+// Every personal weather station comes with the API key of its owner.
+pub struct StationConfig {
+    station_id: String,
+    api_key: String,
+}
+
+// Parses "STATION_ID:API_KEY,STATION_ID:API_KEY,...".
+pub fn config() -> anyhow::Result<Vec<StationConfig>> {
+    spin_sdk::variables::get("wunderground_stations")?
+        .split(',')
+        .map(|entry| entry.trim())
+        .filter(|entry| !entry.is_empty())
+        .map(|entry| match entry.split_once(':') {
+            Some((station_id, api_key)) => Ok(StationConfig {
+                station_id: station_id.trim().to_owned(),
+                api_key: api_key.trim().to_owned(),
+            }),
+            None => anyhow::bail!("Invalid station entry, 'STATION_ID:API_KEY' expected"),
+        })
+        .collect()
+}
+
+pub async fn fetch_data(
+    config: &[StationConfig],
+) -> anyhow::Result<(Vec<Station>, Vec<Measurement>)> {
+    println!("[wunderground] Fetching...");
+    let mut available_stations: Vec<Station> = vec![];
+    let mut measurements: Vec<Measurement> = vec![];
+
+    for station_config in config {
+        // Imperial units, as the metric ones are rounded to integers.
+        let url = format!(
+            "{}?stationId={}&format=json&units=e&numericPrecision=decimal&apiKey={}",
+            API_URL, station_config.station_id, station_config.api_key
+        );
+        match fetch_utf8(&url).await.and_then(|body| parse_current(&body)) {
+            Ok(Some((station, measurement))) => {
+                available_stations.push(station);
+                measurements.push(measurement);
+            }
+            Ok(None) => println!(
+                "[{}] Wind information not available",
+                station_config.station_id
+            ),
+            Err(e) => println!("[{}] {}", station_config.station_id, e),
+        }
+    }
+
+    Ok((available_stations, measurements))
+}
+
+fn parse_current(body: &str) -> anyhow::Result<Option<(Station, Measurement)>> {
+    let current: CurrentRaw = serde_json::from_str(body)?;
+    let observation = current
+        .observations
+        .into_iter()
+        .next()
+        .ok_or_else(|| anyhow::anyhow!("No observations found"))?;
+    convert(observation)
+}
+
+fn convert(observation: ObservationRaw) -> anyhow::Result<Option<(Station, Measurement)>> {
+    let imperial = observation.imperial;
+    let Some(wind_speed) = imperial.windSpeed.map(|v| mph_to_kmh(v).round() as u64) else {
+        return Ok(None);
+    };
+    let last_update = DateTime::parse_from_rfc3339(&observation.obsTimeUtc)
+        .map_err(|e| anyhow::anyhow!("Invalid time '{}': {}", observation.obsTimeUtc, e))?
+        .with_timezone(&Utc)
+        .format("%Y-%m-%dT%H:%MZ")
+        .to_string();
+
+    let station_url = format!("{}/{}", STATION_BASE_URL, observation.stationID);
+    let station_id = station_id_of("wunderground", &observation.stationID);
+
+    let measurement = Measurement {
+        station_id: station_id.clone(),
+        wind_speed,
+        wind_direction: observation.winddir.filter(|_| wind_speed != 0),
+        gusts_speed: imperial.windGust.map(|v| mph_to_kmh(v).round() as u64),
+        temperature: imperial.temp.map(fahrenheit_to_celsius),
+        humidity: observation.humidity,
+        pressure: imperial.pressure.map(inhg_to_hpa),
+        precipitation: imperial.precipTotal.map(inches_to_mm),
+        last_update,
+        ..Default::default()
+    };
+    let station = Station {
+        id: station_id,
+        name: observation
+            .neighborhood
+            .filter(|name| !name.is_empty())
+            .unwrap_or_else(|| observation.stationID.to_owned()),
+        elevation: imperial.elev.map(|elev| feet_to_m(elev).round() as i64),
+        url: station_url,
+        lat: observation.lat,
+        long: observation.lon,
+        available: true,
+        provider: "wunderground".to_owned(),
+        vendor_id: observation.stationID.to_owned(),
+        alternates: vec![],
+    };
+    Ok(Some((station, measurement)))
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    fn assert_close(actual: Option<f64>, expected: f64) {
+        let actual = actual.expect("value expected");
+        assert!(
+            (actual - expected).abs() < 0.01,
+            "{} != {}",
+            actual,
+            expected
+        );
+    }
+
+    #[test]
+    fn parse_current_converts_imperial_units() {
+        let body = include_str!("../tests/fixtures/wunderground_current.json");
+        let (station, measurement) = parse_current(body).unwrap().unwrap();
+
+        assert_eq!(station.id, station_id_of("wunderground", "IOSONA12"));
+        assert_eq!(station.name, "Collsuspina");
+        assert_eq!(
+            station.url,
+            "https://www.wunderground.com/dashboard/pws/IOSONA12"
+        );
+        // 2706 ft
+        assert_eq!(station.elevation, Some(825));
+        assert_eq!((station.lat, station.long), (41.8262, 2.1753));
+
+        assert_eq!(measurement.last_update, "2024-05-12T10:14Z");
+        // 10 mph and 18.5 mph
+        assert_eq!(measurement.wind_speed, 16);
+        assert_eq!(measurement.gusts_speed, Some(30));
+        assert_eq!(measurement.wind_direction, Some(315.0));
+        // 59 °F, 30.01 inHg and 0.12 in
+        assert_close(measurement.temperature, 15.0);
+        assert_close(measurement.pressure, 1016.25);
+        assert_close(measurement.precipitation, 3.05);
+        assert_eq!(measurement.humidity, Some(48.0));
+    }
+
+    #[test]
+    fn parse_current_skips_station_without_wind() {
+        let body = include_str!("../tests/fixtures/wunderground_current_no_wind.json");
+        assert!(parse_current(body).unwrap().is_none());
+    }
+
+    #[test]
+    fn parse_current_requires_observation() {
+        assert!(parse_current(r#"{"observations":[]}"#).is_err());
+    }
+}